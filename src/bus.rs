@@ -0,0 +1,328 @@
+use crate::{CommandNotify, Driver, MultipleDeviceDriver};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// 多个逻辑设备共用的一条总线，由 [`BusDriver`] 适配为驱动
+///
+/// 总线本身是一个物理设备，占用一个键和一个线程；总线上的逻辑设备以地址区分。
+pub trait Bus: 'static + Send + Sized {
+    type Key: Send + 'static;
+    type Address: Clone + Eq + Hash + Send;
+    type Event;
+    type Command: Send;
+
+    /// 所有可能的总线，见 [`Driver::keys`]
+    fn keys() -> Vec<Self::Key>;
+    fn open_timeout() -> Duration;
+
+    /// 打开总线
+    fn open(t: &Self::Key) -> Option<Self>;
+
+    /// 监听总线，事件标有产生它的逻辑设备的地址，语义同 [`Driver::join`]
+    fn join<F>(&mut self, f: F) -> bool
+    where
+        F: FnMut(&mut Self, Option<(Instant, Self::Address, Self::Event)>) -> bool;
+
+    /// 向 `address` 处的逻辑设备发送指令
+    fn send(&mut self, address: &Self::Address, command: Self::Command);
+
+    /// 逻辑设备超过这个时长不产生事件即视作断连
+    ///
+    /// 超时只在总线调用回调时检查，总线上没有事件时也应该定期以 `None` 调用回调，否则无法报告断连。
+    #[inline]
+    fn address_timeout() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    /// 见 [`MultipleDeviceDriver::set_command_notify`]
+    #[inline]
+    fn set_command_notify(&mut self, _notify: CommandNotify) {}
+}
+
+/// 总线上逻辑设备的事件
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BusEvent<A, E> {
+    /// 逻辑设备首次产生事件，或断连后再次产生事件，在它的 [`Event`](Self::Event) 之前产生
+    Attached(A),
+    /// 逻辑设备产生的事件
+    Event(A, E),
+    /// 逻辑设备超过 [`Bus::address_timeout`] 没有产生事件
+    Detached(A),
+}
+
+/// 将总线适配为驱动，总线上的逻辑设备各自连接和断连，总线保持打开
+///
+/// 驱动的事件是 [`BusEvent`]，指令是地址和指令的组合。总线因错误退出时，其上所有逻辑设备随之断连。
+/// 监听事件期间，回调得到的驱动不持有总线，此时发送的指令将在回调返回后转发，
+/// 即使回调要求退出或总线随后因错误退出，也会先交给总线。
+pub struct BusDriver<B: Bus> {
+    bus: Option<B>,
+    pending: Vec<(B::Address, B::Command)>,
+    /// 在线的逻辑设备及其最后一次产生事件的时刻
+    attached: HashMap<B::Address, Instant>,
+}
+
+impl<B: Bus> BusDriver<B> {
+    /// 取得总线，监听事件期间返回 `None`
+    #[inline]
+    pub fn bus(&self) -> Option<&B> {
+        self.bus.as_ref()
+    }
+
+    /// 取得总线的可变引用，监听事件期间返回 `None`
+    #[inline]
+    pub fn bus_mut(&mut self) -> Option<&mut B> {
+        self.bus.as_mut()
+    }
+
+    /// 产生事件的逻辑设备
+    #[inline]
+    pub fn attached(&self) -> impl Iterator<Item = &B::Address> {
+        self.attached.keys()
+    }
+
+    /// 检查逻辑设备是否超时，返回超时的地址
+    fn expired(&self, now: Instant) -> Vec<B::Address> {
+        let timeout = B::address_timeout();
+        self.attached
+            .iter()
+            .filter(|(_, t)| now.saturating_duration_since(**t) >= timeout)
+            .map(|(a, _)| a.clone())
+            .collect()
+    }
+}
+
+impl<B: Bus> Driver for BusDriver<B> {
+    type Pacemaker = ();
+    type Key = B::Key;
+    type Event = BusEvent<B::Address, B::Event>;
+
+    #[inline]
+    fn keys() -> Vec<Self::Key> {
+        B::keys()
+    }
+
+    #[inline]
+    fn open_timeout() -> Duration {
+        B::open_timeout()
+    }
+
+    fn new(t: &Self::Key) -> Option<(Self::Pacemaker, Self)> {
+        Some((
+            (),
+            Self {
+                bus: Some(B::open(t)?),
+                pending: Vec::new(),
+                attached: HashMap::new(),
+            },
+        ))
+    }
+
+    fn join<F>(&mut self, mut f: F) -> bool
+    where
+        F: FnMut(&mut Self, Option<(Instant, Self::Event)>) -> bool,
+    {
+        use BusEvent::*;
+
+        // 监听期间取出总线，结束后放回
+        let mut bus = self.bus.take().expect("BusDriver is already joining");
+        let ok = bus.join(|bus, e| {
+            let now = Instant::now();
+            // 回调要求退出时，尚未报告的超时留待下次报告
+            let mut keep = true;
+            for a in self.expired(now) {
+                self.attached.remove(&a);
+                if !f(self, Some((now, Detached(a)))) {
+                    keep = false;
+                    break;
+                }
+            }
+            keep = keep
+                && match e {
+                    Some((t, a, e)) => {
+                        // 回调接受了上线的逻辑设备才记录，否则下次仍报告上线
+                        let attached = self.attached.contains_key(&a)
+                            || f(self, Some((t, Attached(a.clone()))));
+                        if attached {
+                            self.attached.insert(a.clone(), now);
+                        }
+                        attached && f(self, Some((t, Event(a, e))))
+                    }
+                    None => f(self, None),
+                };
+            for (a, c) in self.pending.drain(..) {
+                bus.send(&a, c);
+            }
+            keep
+        });
+        self.bus = Some(bus);
+        if !ok {
+            self.attached.clear();
+        }
+        ok
+    }
+}
+
+impl<B: Bus> MultipleDeviceDriver for BusDriver<B> {
+    type Command = (B::Address, B::Command);
+
+    fn send(&mut self, (address, command): Self::Command) {
+        match &mut self.bus {
+            Some(bus) => bus.send(&address, command),
+            None => self.pending.push((address, command)),
+        }
+    }
+
+    fn set_command_notify(&mut self, notify: CommandNotify) {
+        if let Some(bus) = &mut self.bus {
+            bus.set_command_notify(notify);
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// 总线上依次发生的情况
+    enum Step {
+        Event(u8),
+        Tick,
+        Sleep(u64),
+    }
+
+    /// 按脚本产生事件的总线，记录收到的指令
+    #[derive(Default)]
+    struct Script {
+        steps: VecDeque<Step>,
+        sent: Vec<(u8, u8)>,
+    }
+
+    impl Bus for Script {
+        type Key = u8;
+        type Address = u8;
+        type Event = ();
+        type Command = u8;
+
+        fn keys() -> Vec<Self::Key> {
+            vec![0]
+        }
+
+        fn open_timeout() -> Duration {
+            Duration::ZERO
+        }
+
+        fn open(_t: &Self::Key) -> Option<Self> {
+            Some(Self::default())
+        }
+
+        fn join<F>(&mut self, mut f: F) -> bool
+        where
+            F: FnMut(&mut Self, Option<(Instant, Self::Address, Self::Event)>) -> bool,
+        {
+            while let Some(step) = self.steps.pop_front() {
+                let keep = match step {
+                    Step::Event(a) => f(self, Some((Instant::now(), a, ()))),
+                    Step::Tick => f(self, None),
+                    Step::Sleep(ms) => {
+                        std::thread::sleep(Duration::from_millis(ms));
+                        true
+                    }
+                };
+                if !keep {
+                    break;
+                }
+            }
+            true
+        }
+
+        fn send(&mut self, address: &Self::Address, command: Self::Command) {
+            self.sent.push((*address, command));
+        }
+
+        fn address_timeout() -> Duration {
+            Duration::from_millis(10)
+        }
+    }
+
+    fn open(steps: impl IntoIterator<Item = Step>) -> BusDriver<Script> {
+        let (_, mut driver) = BusDriver::<Script>::new(&0).unwrap();
+        driver.bus_mut().unwrap().steps = steps.into_iter().collect();
+        driver
+    }
+
+    #[test]
+    fn attach() {
+        use BusEvent::*;
+
+        let mut driver = open([Step::Event(1), Step::Event(1), Step::Event(2)]);
+        let mut events = Vec::new();
+        assert!(driver.join(|_, e| {
+            events.push(e.map(|(_, e)| e));
+            true
+        }));
+        assert_eq!(
+            events,
+            [
+                Some(Attached(1)),
+                Some(Event(1, ())),
+                Some(Event(1, ())),
+                Some(Attached(2)),
+                Some(Event(2, ())),
+            ]
+        );
+        let mut attached = driver.attached().copied().collect::<Vec<_>>();
+        attached.sort();
+        assert_eq!(attached, [1, 2]);
+    }
+
+    #[test]
+    fn attach_refused() {
+        use BusEvent::*;
+
+        // 回调拒绝上线后，再次产生事件时仍然报告上线
+        let mut driver = open([Step::Event(1), Step::Event(1)]);
+        let mut events = Vec::new();
+        driver.join(|_, e| {
+            events.push(e.map(|(_, e)| e));
+            false
+        });
+        assert_eq!(driver.attached().count(), 0);
+        driver.join(|_, e| {
+            events.push(e.map(|(_, e)| e));
+            true
+        });
+        assert_eq!(
+            events,
+            [Some(Attached(1)), Some(Attached(1)), Some(Event(1, ()))]
+        );
+    }
+
+    #[test]
+    fn detach() {
+        use BusEvent::*;
+
+        let mut driver = open([Step::Event(1), Step::Sleep(20), Step::Tick]);
+        let mut events = Vec::new();
+        driver.join(|d, e| {
+            let e = e.map(|(_, e)| e);
+            // 断连时发送的指令在回调要求退出后仍然交给总线
+            let keep = !matches!(e, Some(Detached(_)));
+            if !keep {
+                d.send((1, 7));
+            }
+            events.push(e);
+            keep
+        });
+        assert_eq!(
+            events,
+            [Some(Attached(1)), Some(Event(1, ())), Some(Detached(1))]
+        );
+        assert_eq!(driver.attached().count(), 0);
+        assert_eq!(driver.bus().unwrap().sent, [(1, 7)]);
+    }
+}
@@ -0,0 +1,67 @@
+use core::ops::{BitOr, BitOrAssign};
+
+/// 驱动声明支持的功能，见 [`Driver::CAPABILITIES`](crate::Driver::CAPABILITIES)
+///
+/// 只是声明，监控器不依据它改变行为，供工具和界面在运行时区分驱动。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// 不声明任何功能
+    pub const NONE: Self = Self(0);
+    /// 可以接收指令
+    pub const COMMANDS: Self = Self(1 << 0);
+    /// 需要起搏器定时触发
+    pub const PACEMAKER: Self = Self(1 << 1);
+    /// 断连后可以重新打开
+    pub const RECONNECT: Self = Self(1 << 2);
+    /// 能提供设备的唯一标识
+    pub const IDENTITY: Self = Self(1 << 3);
+
+    /// 合并两组功能
+    #[inline]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// 判断是否包含 `other` 中的所有功能
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[inline]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+impl BitOrAssign for Capabilities {
+    #[inline]
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = self.union(rhs);
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let caps = Capabilities::COMMANDS | Capabilities::RECONNECT;
+        assert!(caps.contains(Capabilities::COMMANDS));
+        assert!(caps.contains(Capabilities::NONE));
+        assert!(!caps.contains(Capabilities::COMMANDS | Capabilities::PACEMAKER));
+        assert!(Capabilities::default().is_empty());
+    }
+}
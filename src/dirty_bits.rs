@@ -0,0 +1,129 @@
+use alloc::{vec, vec::Vec};
+
+/// 按位压缩存储的脏标记集合
+///
+/// 每个下标占用一位，所有操作都会检查下标是否越界。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirtyBits {
+    bits: Vec<u8>,
+    capacity: usize,
+}
+
+impl DirtyBits {
+    /// 创建容纳 `capacity` 个标记的集合，所有标记均未设置
+    #[inline]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            bits: vec![0; capacity.div_ceil(8)],
+            capacity,
+        }
+    }
+
+    /// 集合能容纳的标记数
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// 设置标记 `i`
+    #[inline]
+    pub fn set(&mut self, i: usize) {
+        self.check(i);
+        self.bits[i / 8] |= 1 << (i % 8);
+    }
+
+    /// 清除标记 `i`，返回清除前是否已设置
+    pub fn clear(&mut self, i: usize) -> bool {
+        self.check(i);
+        let block = &mut self.bits[i / 8];
+        let mask = 1 << (i % 8);
+        let result = (*block & mask) != 0;
+        *block &= !mask;
+        result
+    }
+
+    /// 判断标记 `i` 是否已设置
+    #[inline]
+    pub fn is_set(&self, i: usize) -> bool {
+        self.check(i);
+        (self.bits[i / 8] & (1 << (i % 8))) != 0
+    }
+
+    /// 按下标升序遍历所有已设置的标记
+    pub fn iter_set(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.capacity).filter(move |&i| self.is_set(i))
+    }
+
+    /// 清除所有标记
+    #[inline]
+    pub fn clear_all(&mut self) {
+        self.bits.fill(0);
+    }
+
+    /// 缩小容量到 `capacity`，丢弃超出的标记，容量不小于当前容量时无事发生
+    pub fn truncate(&mut self, capacity: usize) {
+        if capacity >= self.capacity {
+            return;
+        }
+        self.capacity = capacity;
+        self.bits.truncate(capacity.div_ceil(8));
+        if capacity % 8 != 0 {
+            *self.bits.last_mut().unwrap() &= (1 << (capacity % 8)) - 1;
+        }
+    }
+
+    #[inline]
+    fn check(&self, i: usize) {
+        assert!(
+            i < self.capacity,
+            "index out of bounds: the capacity is {} but the index is {}",
+            self.capacity,
+            i
+        );
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let mut bits = DirtyBits::new(10);
+        assert_eq!(bits.capacity(), 10);
+        assert_eq!(bits.iter_set().count(), 0);
+        // 设置
+        bits.set(0);
+        bits.set(7);
+        bits.set(9);
+        assert!(bits.is_set(7));
+        assert!(!bits.is_set(8));
+        assert_eq!(bits.iter_set().collect::<Vec<_>>(), vec![0, 7, 9]);
+        // 清除
+        assert!(bits.clear(7));
+        assert!(!bits.clear(7));
+        assert_eq!(bits.iter_set().collect::<Vec<_>>(), vec![0, 9]);
+        bits.clear_all();
+        assert_eq!(bits.iter_set().count(), 0);
+    }
+
+    #[test]
+    fn truncate() {
+        let mut bits = DirtyBits::new(20);
+        bits.set(3);
+        bits.set(9);
+        bits.set(12);
+        bits.truncate(10);
+        assert_eq!(bits.capacity(), 10);
+        assert_eq!(bits.iter_set().collect::<Vec<_>>(), vec![3, 9]);
+        // 重新扩大后，被丢弃的标记不会恢复
+        bits.truncate(30);
+        assert_eq!(bits.capacity(), 10);
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_bounds() {
+        DirtyBits::new(10).set(10);
+    }
+}
@@ -0,0 +1,408 @@
+use crate::{
+    Capabilities, CommandNotify, Driver, DriverPacemaker, MultipleDeviceDriver, PacemakerHandle,
+    RejectReason,
+};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// 两种值之一，用作组合驱动的键、事件和指令
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+/// 将两种驱动组合为一种驱动，组合更多种驱动时可以嵌套
+///
+/// 组合驱动的键、事件和指令都是 [`Either`]，指令按分支转发给对应的驱动，不匹配的指令将被丢弃。
+/// 内部驱动的起搏器在打开时启动，在组合驱动关闭或丢弃时停止，其失败经 [`EitherPacemaker`] 报告给监控器，
+/// 其保活指令经 [`EitherPacemaker`] 送出。被内部驱动过滤的事件以 `None` 交给回调。
+///
+/// 监听事件期间，回调得到的组合驱动不持有内部驱动，此时发送的指令将在回调返回后转发。
+pub struct EitherDriver<A: MultipleDeviceDriver, B: MultipleDeviceDriver> {
+    driver: Option<Either<A, B>>,
+    pending: Vec<Either<A::Command, B::Command>>,
+    pacemaker: Option<InnerPacemaker<A, B>>,
+}
+
+type InnerPacemaker<A, B> =
+    Either<PacemakerHandle<<A as Driver>::Pacemaker>, PacemakerHandle<<B as Driver>::Pacemaker>>;
+
+/// 组合驱动的起搏器，跟随内部驱动的起搏器停止，内部起搏器失败时随之失败
+pub struct EitherPacemaker<A: MultipleDeviceDriver, B: MultipleDeviceDriver> {
+    inner: InnerPacemaker<A, B>,
+    /// 内部起搏器发送了触发脉冲，尚未产生保活指令
+    due: Arc<AtomicBool>,
+}
+
+impl<A: MultipleDeviceDriver, B: MultipleDeviceDriver> DriverPacemaker for EitherPacemaker<A, B> {
+    /// 检查内部起搏器的周期，不超过 1 秒
+    #[inline]
+    fn period() -> Duration {
+        A::Pacemaker::period()
+            .min(B::Pacemaker::period())
+            .min(Duration::from_secs(1))
+    }
+
+    fn send(&mut self) -> io::Result<bool> {
+        let (stopped, failed) = match &self.inner {
+            Either::Left(p) => (p.is_stopped(), p.is_failed()),
+            Either::Right(p) => (p.is_stopped(), p.is_failed()),
+        };
+        if failed {
+            Err(io::Error::other("inner pacemaker failed"))
+        } else {
+            Ok(!stopped)
+        }
+    }
+}
+
+impl<A: MultipleDeviceDriver, B: MultipleDeviceDriver> EitherDriver<A, B> {
+    /// 取得内部驱动，监听事件期间返回 `None`
+    #[inline]
+    pub fn get(&self) -> Option<Either<&A, &B>> {
+        match self.driver.as_ref()? {
+            Either::Left(a) => Some(Either::Left(a)),
+            Either::Right(b) => Some(Either::Right(b)),
+        }
+    }
+
+    /// 取得内部驱动的可变引用，监听事件期间返回 `None`
+    #[inline]
+    pub fn get_mut(&mut self) -> Option<Either<&mut A, &mut B>> {
+        match self.driver.as_mut()? {
+            Either::Left(a) => Some(Either::Left(a)),
+            Either::Right(b) => Some(Either::Right(b)),
+        }
+    }
+
+    /// 停止内部驱动的起搏器
+    fn stop_pacemaker(&mut self) {
+        match self.pacemaker.take() {
+            Some(Either::Left(p)) => p.stop(),
+            Some(Either::Right(p)) => p.stop(),
+            None => {}
+        }
+    }
+}
+
+/// 内部起搏器每次发送触发脉冲后标记 `due`，组合起搏器丢弃后不再标记
+fn mark_due<P>(pacemaker: &PacemakerHandle<P>, due: &Arc<AtomicBool>) {
+    let due = Arc::downgrade(due);
+    pacemaker.set_hook(move |_| match due.upgrade() {
+        Some(due) => {
+            due.store(true, Ordering::Release);
+            true
+        }
+        None => false,
+    });
+}
+
+impl<A: MultipleDeviceDriver, B: MultipleDeviceDriver> From<Either<A, B>> for EitherDriver<A, B> {
+    #[inline]
+    fn from(driver: Either<A, B>) -> Self {
+        Self {
+            driver: Some(driver),
+            pending: Vec::new(),
+            pacemaker: None,
+        }
+    }
+}
+
+impl<A, B> Driver for EitherDriver<A, B>
+where
+    A: MultipleDeviceDriver,
+    B: MultipleDeviceDriver,
+    A::Command: Send,
+    B::Command: Send,
+{
+    type Pacemaker = EitherPacemaker<A, B>;
+    type Key = Either<A::Key, B::Key>;
+    type Event = Either<A::Event, B::Event>;
+
+    const CAPABILITIES: Capabilities = A::CAPABILITIES.union(B::CAPABILITIES);
+
+    fn keys() -> Vec<Self::Key> {
+        A::keys()
+            .into_iter()
+            .map(Either::Left)
+            .chain(B::keys().into_iter().map(Either::Right))
+            .collect()
+    }
+
+    fn scan() -> Vec<Self::Key> {
+        A::scan()
+            .into_iter()
+            .map(Either::Left)
+            .chain(B::scan().into_iter().map(Either::Right))
+            .collect()
+    }
+
+    #[inline]
+    fn open_timeout() -> Duration {
+        A::open_timeout().max(B::open_timeout())
+    }
+
+    #[inline]
+    fn discover_timeout() -> Duration {
+        A::discover_timeout().max(B::discover_timeout())
+    }
+
+    #[inline]
+    fn validate_timeout() -> Duration {
+        A::validate_timeout().max(B::validate_timeout())
+    }
+
+    #[inline]
+    fn new_timeout() -> Duration {
+        A::new_timeout().max(B::new_timeout())
+    }
+
+    #[inline]
+    fn validate_without_timeout() -> bool {
+        A::validate_without_timeout() || B::validate_without_timeout()
+    }
+
+    #[inline]
+    fn min_events() -> usize {
+        A::min_events().max(B::min_events())
+    }
+
+    #[inline]
+    fn max_probes() -> usize {
+        A::max_probes().min(B::max_probes())
+    }
+
+    fn new(t: &Self::Key) -> Option<(Self::Pacemaker, Self)> {
+        let (pacemaker, driver) =
+            match t {
+                Either::Left(k) => A::new(k)
+                    .map(|(p, d)| (Either::Left(PacemakerHandle::spawn(p)), Either::Left(d))),
+                Either::Right(k) => B::new(k)
+                    .map(|(p, d)| (Either::Right(PacemakerHandle::spawn(p)), Either::Right(d))),
+            }?;
+        let due = Arc::new(AtomicBool::new(false));
+        match &pacemaker {
+            Either::Left(p) => mark_due(p, &due),
+            Either::Right(p) => mark_due(p, &due),
+        }
+        Some((
+            EitherPacemaker {
+                inner: pacemaker.clone(),
+                due,
+            },
+            Self {
+                driver: Some(driver),
+                pending: Vec::new(),
+                pacemaker: Some(pacemaker),
+            },
+        ))
+    }
+
+    fn join<F>(&mut self, mut f: F) -> bool
+    where
+        F: FnMut(&mut Self, Option<(Instant, Self::Event)>) -> bool,
+    {
+        // 监听期间取出内部驱动，结束后放回
+        let mut driver = self.driver.take().expect("EitherDriver is already joining");
+        let ok = match &mut driver {
+            Either::Left(a) => a.join(|a, e| {
+                let e = e.filter(|(_, e)| a.filter_event(e));
+                let ok = f(self, e.map(|(t, e)| (t, Either::Left(e))));
+                for c in self.pending.drain(..) {
+                    if let Either::Left(c) = c {
+                        a.send(c);
+                    }
+                }
+                ok
+            }),
+            Either::Right(b) => b.join(|b, e| {
+                let e = e.filter(|(_, e)| b.filter_event(e));
+                let ok = f(self, e.map(|(t, e)| (t, Either::Right(e))));
+                for c in self.pending.drain(..) {
+                    if let Either::Right(c) = c {
+                        b.send(c);
+                    }
+                }
+                ok
+            }),
+        };
+        self.driver = Some(driver);
+        ok
+    }
+
+    fn rejected(t: &Self::Key, reason: RejectReason) {
+        match t {
+            Either::Left(k) => A::rejected(k, reason),
+            Either::Right(k) => B::rejected(k, reason),
+        }
+    }
+
+    fn key_label(t: &Self::Key) -> Option<String> {
+        match t {
+            Either::Left(k) => A::key_label(k),
+            Either::Right(k) => B::key_label(k),
+        }
+    }
+
+    fn identity(&self) -> Option<String> {
+        match self.driver.as_ref()? {
+            Either::Left(a) => a.identity(),
+            Either::Right(b) => b.identity(),
+        }
+    }
+
+    fn accepted(t: &Self::Key, latency: Option<Duration>) {
+        match t {
+            Either::Left(k) => A::accepted(k, latency),
+            Either::Right(k) => B::accepted(k, latency),
+        }
+    }
+
+    fn reset(&mut self) -> bool {
+        match &mut self.driver {
+            Some(Either::Left(a)) => a.reset(),
+            Some(Either::Right(b)) => b.reset(),
+            None => false,
+        }
+    }
+
+    fn shutdown(&mut self) {
+        match &mut self.driver {
+            Some(Either::Left(a)) => a.shutdown(),
+            Some(Either::Right(b)) => b.shutdown(),
+            None => {}
+        }
+        self.stop_pacemaker();
+    }
+}
+
+impl<A: MultipleDeviceDriver, B: MultipleDeviceDriver> Drop for EitherDriver<A, B> {
+    #[inline]
+    fn drop(&mut self) {
+        self.stop_pacemaker();
+    }
+}
+
+impl<A, B> MultipleDeviceDriver for EitherDriver<A, B>
+where
+    A: MultipleDeviceDriver,
+    B: MultipleDeviceDriver,
+    A::Command: Send,
+    B::Command: Send,
+{
+    type Command = Either<A::Command, B::Command>;
+
+    fn send(&mut self, command: Self::Command) {
+        match (&mut self.driver, command) {
+            (Some(Either::Left(a)), Either::Left(c)) => a.send(c),
+            (Some(Either::Right(b)), Either::Right(c)) => b.send(c),
+            (None, c) => self.pending.push(c),
+            _ => {}
+        }
+    }
+
+    #[inline]
+    fn command_timeout() -> Duration {
+        A::command_timeout().min(B::command_timeout())
+    }
+
+    /// 内部起搏器发送过触发脉冲时，由内部驱动产生保活指令
+    fn keepalive(pacemaker: &mut Self::Pacemaker) -> Option<Self::Command> {
+        if !pacemaker.due.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        match &pacemaker.inner {
+            Either::Left(p) => A::keepalive(&mut p.lock()).map(Either::Left),
+            Either::Right(p) => B::keepalive(&mut p.lock()).map(Either::Right),
+        }
+    }
+
+    fn send_timeout(
+        &mut self,
+        command: Self::Command,
+        timeout: Duration,
+    ) -> Result<(), Self::Command> {
+        match (&mut self.driver, command) {
+            (Some(Either::Left(a)), Either::Left(c)) => {
+                a.send_timeout(c, timeout).map_err(Either::Left)
+            }
+            (Some(Either::Right(b)), Either::Right(c)) => {
+                b.send_timeout(c, timeout).map_err(Either::Right)
+            }
+            (None, c) => {
+                self.pending.push(c);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn set_command_notify(&mut self, notify: CommandNotify) {
+        match &mut self.driver {
+            Some(Either::Left(a)) => a.set_command_notify(notify),
+            Some(Either::Right(b)) => b.set_command_notify(notify),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    /// 只产生一个事件的设备，至少需要两个事件才能通过验证
+    struct Once;
+
+    impl Driver for Once {
+        type Pacemaker = ();
+        type Key = u8;
+        type Event = ();
+
+        fn keys() -> Vec<Self::Key> {
+            vec![0]
+        }
+
+        fn open_timeout() -> Duration {
+            Duration::from_millis(50)
+        }
+
+        fn min_events() -> usize {
+            2
+        }
+
+        fn new(_t: &Self::Key) -> Option<(Self::Pacemaker, Self)> {
+            Some(((), Self))
+        }
+
+        fn join<F>(&mut self, mut f: F) -> bool
+        where
+            F: FnMut(&mut Self, Option<(Instant, Self::Event)>) -> bool,
+        {
+            let mut ok = f(self, Some((Instant::now(), ())));
+            while ok {
+                std::thread::sleep(Duration::from_millis(1));
+                ok = f(self, None);
+            }
+            true
+        }
+    }
+
+    impl MultipleDeviceDriver for Once {
+        type Command = ();
+
+        fn send(&mut self, _command: Self::Command) {}
+    }
+
+    #[test]
+    fn min_events() {
+        assert_eq!(EitherDriver::<Once, Once>::min_events(), 2);
+        assert!(EitherDriver::<Once, Once>::open_some(1).is_empty());
+    }
+}
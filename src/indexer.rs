@@ -1,44 +1,209 @@
-﻿use std::{
+﻿use crate::DirtyBits;
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
+use core::{
     cmp::Ordering::{self, *},
-    collections::BinaryHeap,
-    ops::Range,
+    fmt,
+    ops::{Range, RangeInclusive},
 };
 
+mod map;
+
+pub use map::IndexerMap;
+
+/// 新键不能放在指定的位置，交还新键
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OutOfOrder<T>(pub T);
+
+/// [`Indexer::add_reporting`] 的结果
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AddResult<'a, T> {
+    /// 新键的位置，进入等待队列时为 `None`
+    pub index: Option<usize>,
+    /// 被新键挤进等待队列的原固定键
+    pub displaced: Option<&'a T>,
+}
+
+/// [`Indexer::probe`] 的结果
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InsertOutcome<'a, T> {
+    /// 新键将放在这个位置
+    Index(usize),
+    /// 新键将进入等待队列
+    Waiting,
+    /// 新键将放在 `index` 处，并把最后一个固定的键挤进等待队列
+    Displaces { index: usize, displaced: &'a T },
+}
+
+/// 排序器已满而新键优先于最后一个固定的键时的处理方式
+pub enum Overflow<T> {
+    /// 最后一个键进入等待队列，固定新键
+    EvictTail,
+    /// 新键进入等待队列，保留已固定的键
+    RejectNew,
+    /// 由闭包决定，参数依次是新键和最后一个键，返回 `true` 时固定新键
+    #[allow(clippy::type_complexity)]
+    Custom(Box<dyn Fn(&T, &T) -> bool + Send + Sync>),
+}
+
+/// 新键与已有的键相等时的处理方式
+pub enum Tie<T> {
+    /// 新键排在相等的键之后，先到先得
+    After,
+    /// 新键排在相等的键之前
+    Before,
+    /// 由闭包进一步比较新键和已有的键，比较方式与排序依据相同，仍然相等时新键排在之后
+    #[allow(clippy::type_complexity)]
+    By(Box<dyn Fn(&T, &T) -> Ordering + Send + Sync>),
+}
+
 /// 仅依据键对设备排序的排序器，用于稳定区分数据上没有区别的两个驱动程序
 pub struct Indexer<T> {
     pinned: Vec<Option<T>>,
-    modified: FlagVec,
-    waiting: BinaryHeap<T>,
+    modified: DirtyBits,
+    /// 按优先级从低到高排列，末尾是最优先的键
+    waiting: Vec<T>,
     len: usize,
     reversed: bool,
+    order: Order<T>,
+    overflow: Overflow<T>,
+    tie: Tie<T>,
+    /// 启用时记录位置的变化
+    changes: Option<Vec<IndexChange>>,
 }
 
-struct FlagVec(Vec<u8>);
+/// 排序器中位置的变化，见 [`Indexer::drain_changes`]
+///
+/// 按记录的顺序依次应用到镜像上，即可得到与排序器相同的布局。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndexChange {
+    /// 键放入此前为空的位置，可能是新添加的键，也可能是从等待队列补充的键
+    Added { index: usize },
+    /// 键离开这个位置，被移除或进入等待队列
+    Removed { index: usize },
+    /// 键从 `from` 移动到此前为空的 `to`
+    Moved { from: usize, to: usize },
+    /// 交换两个位置上的键，见 [`Indexer::swap`]
+    Swapped { a: usize, b: usize },
+}
+
+/// 新键在排序器中的去向
+enum Place {
+    /// 进入等待队列
+    Waiting,
+    /// 放在这个位置，原有的键依次前移到前方的空位
+    Forward(usize),
+    /// 放在 `index` 处，之后的键依次后移到 `hole` 处的空位，`evict` 表示空位需要挤出最后一个键产生
+    Backward {
+        index: usize,
+        hole: usize,
+        evict: bool,
+    },
+}
+
+/// 键的排序依据
+enum Order<T> {
+    /// 键本身的顺序
+    Natural(fn(&T, &T) -> Ordering),
+    /// 从键中取出的部分的顺序
+    #[allow(clippy::type_complexity)]
+    By(Box<dyn Fn(&T, &T) -> Ordering + Send + Sync>),
+}
 
 impl<T> Indexer<T>
 where
     T: Ord,
 {
+    #[inline]
     pub fn new(capacity: usize) -> Self {
+        Self::with_order(capacity, Order::Natural(T::cmp))
+    }
+
+    #[inline]
+    pub fn new_reversed(capacity: usize) -> Self {
+        Self {
+            reversed: true,
+            ..Self::new(capacity)
+        }
+    }
+
+    /// 创建容量为 `capacity` 的排序器并依次添加 `iter` 中的键，放不下的键进入等待队列
+    pub fn from_iter_with_capacity(capacity: usize, iter: impl IntoIterator<Item = T>) -> Self {
+        let mut indexer = Self::new(capacity);
+        for t in iter {
+            indexer.add(t);
+        }
+        indexer
+    }
+}
+
+impl<T> Indexer<T> {
+    /// 创建依据 `key` 取出的部分排序的排序器
+    ///
+    /// 所有比较都只比较取出的部分，包括判断两个键是否重复。
+    #[inline]
+    pub fn new_by<K, F>(capacity: usize, key: F) -> Self
+    where
+        T: 'static,
+        K: Ord + ?Sized,
+        F: Fn(&T) -> &K + Send + Sync + 'static,
+    {
+        let order = Box::new(move |a: &T, b: &T| key(a).cmp(key(b)));
+        Self::with_order(capacity, Order::By(order))
+    }
+
+    fn with_order(capacity: usize, order: Order<T>) -> Self {
         let mut pinned = Vec::with_capacity(capacity);
         for _ in 0..capacity {
             pinned.push(None);
         }
         Self {
             pinned,
-            modified: FlagVec::with_capacity(capacity),
-            waiting: Default::default(),
+            modified: DirtyBits::new(capacity),
+            waiting: Vec::new(),
             len: 0,
             reversed: false,
+            order,
+            overflow: Overflow::EvictTail,
+            tie: Tie::After,
+            changes: None,
         }
     }
 
+    /// 设置排序器已满时的处理方式，默认为 [`Overflow::EvictTail`]
+    ///
+    /// 只影响 [`add`](Self::add)。保留已固定的键时，等待队列中可能有优先于固定键的键，
+    /// 出现空位时它们按顺序插入。
     #[inline]
-    pub fn new_reversed(capacity: usize) -> Self {
-        Self {
-            reversed: true,
-            ..Self::new(capacity)
-        }
+    pub fn with_overflow(mut self, overflow: Overflow<T>) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// 记录每次添加、移除和移动键引起的位置变化，通过 [`drain_changes`](Self::drain_changes) 取出
+    #[inline]
+    pub fn with_change_log(mut self) -> Self {
+        self.changes = Some(Vec::new());
+        self
+    }
+
+    /// 取出记录的位置变化，未启用记录时为空
+    ///
+    /// 添加的键只给出位置，可以在应用变化后从排序器的同一位置读取。
+    #[inline]
+    pub fn drain_changes(&mut self) -> Vec<IndexChange> {
+        self.changes
+            .as_mut()
+            .map(core::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// 设置新键与已有的键相等时的处理方式，默认为 [`Tie::After`]
+    ///
+    /// 存在相等的键时，[`remove`](Self::remove) 和 [`find`](Self::find) 只处理其中一个。
+    #[inline]
+    pub fn with_tie(mut self, tie: Tie<T>) -> Self {
+        self.tie = tie;
+        self
     }
 
     #[inline]
@@ -56,23 +221,105 @@ where
         self.len
     }
 
+    /// 可以固定的键数
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.pinned.len()
+    }
+
+    /// 等待队列中的键数
+    #[inline]
+    pub fn waiting_len(&self) -> usize {
+        self.waiting.len()
+    }
+
+    /// 已固定的键数占容量的比例，容量为 0 时视作已满
+    #[inline]
+    pub fn occupancy_ratio(&self) -> f32 {
+        if self.pinned.is_empty() {
+            1.0
+        } else {
+            self.len as f32 / self.pinned.len() as f32
+        }
+    }
+
+    #[inline]
     pub fn add(&mut self, t: T) -> Option<usize> {
-        let tail = self.pinned.len() - 1;
+        self.insert(t).0
+    }
+
+    /// 与 [`add`](Self::add) 相同，同时报告因此进入等待队列的原固定键
+    pub fn add_reporting(&mut self, t: T) -> AddResult<'_, T> {
+        let (index, displaced) = self.insert(t);
+        AddResult {
+            index,
+            displaced: displaced.map(|i| &self.waiting[i]),
+        }
+    }
+
+    /// 不修改排序器，预测添加 t 的结果
+    ///
+    /// 与 [`add`](Self::add) 的判断逻辑相同。
+    pub fn probe(&self, t: &T) -> InsertOutcome<'_, T> {
+        match self.locate(t) {
+            Place::Waiting => InsertOutcome::Waiting,
+            Place::Forward(index)
+            | Place::Backward {
+                index,
+                evict: false,
+                ..
+            } => InsertOutcome::Index(index),
+            Place::Backward {
+                index,
+                hole,
+                evict: true,
+            } => InsertOutcome::Displaces {
+                index,
+                displaced: self.get_value(hole),
+            },
+        }
+    }
+
+    /// 添加 t，返回 t 的位置和被挤出的键在等待队列中的位置
+    fn insert(&mut self, t: T) -> (Option<usize>, Option<usize>) {
+        match self.locate(&t) {
+            Place::Waiting => {
+                self.push_waiting(t);
+                (None, None)
+            }
+            Place::Forward(i) => {
+                self.put_somewhere_forward(i, t);
+                (Some(i), None)
+            }
+            Place::Backward { index, hole, evict } => {
+                // 最后一项进入等待队列，在 hole 产生一个空位
+                let displaced = evict.then(|| {
+                    let item = self.get_mut(hole).take().unwrap();
+                    self.log(IndexChange::Removed { index: hole });
+                    self.len -= 1;
+                    self.push_waiting(item)
+                });
+                self.put_backward(index..hole, t);
+                (Some(index), displaced)
+            }
+        }
+    }
+
+    /// 寻找 t 应在的位置
+    fn locate(&self, t: &T) -> Place {
+        // 容量为 0，只能等待
+        let Some(tail) = self.pinned.len().checked_sub(1) else {
+            return Place::Waiting;
+        };
         // 没有空位，检查 t 是否应该等待
-        let mut hole = if self.is_full() {
-            match self.cmp(&t, self.get_value(tail)) {
-                Less => {
-                    // t 进入等待队列，无事发生
-                    self.waiting.push(t);
-                    return None;
-                }
-                Greater => {
-                    // 最后一项进入等待队列，在 tail 产生一个空位
-                    let item = std::mem::replace(self.get_mut(tail), None).unwrap();
-                    self.waiting.push(item);
-                    tail
-                }
-                Equal => panic!("不应该有两个 key 相同的驱动设备"),
+        let (mut hole, evict) = if self.is_full() {
+            match self.cmp_new(t, self.get_value(tail)) {
+                // t 进入等待队列，无事发生
+                Less => return Place::Waiting,
+                // 保留已固定的键，t 进入等待队列
+                _ if !self.overflow.accept(t, self.get_value(tail)) => return Place::Waiting,
+                // 最后一项将进入等待队列，在 tail 产生一个空位
+                _ => (tail, true),
             }
         }
         // 有空位，检查 t 是否在空位之后
@@ -80,16 +327,11 @@ where
             let mut i = tail;
             loop {
                 match self.get(i) {
-                    Some(it) => match self.cmp(&t, it) {
-                        Less => {
-                            // t 已放在 i 处
-                            self.put_somewhere_forward(i, t);
-                            return Some(i);
-                        }
-                        Greater => i -= 1,
-                        Equal => panic!("不应该有两个 key 相同的驱动设备"),
+                    Some(it) => match self.cmp_new(t, it) {
+                        Less => return Place::Forward(i),
+                        Greater | Equal => i -= 1,
                     },
-                    None => break i,
+                    None => break (i, false),
                 }
             }
         };
@@ -98,53 +340,207 @@ where
         while i > 0 {
             i -= 1;
             match self.get(i) {
-                Some(ref item) => match self.cmp(&t, item) {
-                    Less => {
+                Some(ref item) => {
+                    if self.cmp_new(t, item) == Less {
                         i += 1;
                         break;
                     }
-                    Greater => {}
-                    Equal => panic!("不应该有两个 key 相同的驱动设备"),
-                },
+                }
                 None => hole = i,
             }
         }
-        // t 已放在 i 处
-        self.put_backward(i..hole, t);
-        Some(i)
+        Place::Backward {
+            index: i,
+            hole,
+            evict,
+        }
+    }
+
+    /// 从 `hint` 处开始寻找 t 应在的位置
+    ///
+    /// 如果 `hint` 处是空位，且 t 恰好应插入这段连续的空位，则无需扫描即可放置；否则退化为 [`add`](Self::add)。
+    pub fn add_hint(&mut self, t: T, hint: usize) -> Option<usize> {
+        if hint < self.pinned.len() && self.get(hint).is_none() {
+            // 找到空位段两端的键
+            let front = (0..hint).rev().find(|&i| self.get(i).is_some());
+            let back = (hint + 1..self.pinned.len()).find(|&i| self.get(i).is_some());
+            if front.is_none_or(|i| self.cmp(&t, self.get_value(i)) == Less)
+                && back.is_none_or(|i| self.cmp(&t, self.get_value(i)) == Greater)
+            {
+                // 与 add 相同，放在空位段的开头
+                let i = front.map_or(0, |i| i + 1);
+                *self.get_mut(i) = Some(t);
+                self.log(IndexChange::Added { index: i });
+                self.modified.clear(i);
+                self.len += 1;
+                return Some(i);
+            }
+        }
+        self.add(t)
     }
 
     pub fn remove(&mut self, t: &T) -> Option<usize> {
-        let tail = self.pinned.len() - 1;
-        for i in (0..=tail).rev() {
+        for i in (0..self.pinned.len()).rev() {
             if let Some(ref item) = self.get(i) {
                 match self.cmp(t, item) {
-                    Equal => {
-                        return match self.waiting.pop() {
-                            Some(t) => {
-                                self.put_forward(i..tail, t);
-                                None
-                            }
-                            None => {
-                                self.remove_at(i);
-                                Some(i)
-                            }
-                        };
-                    }
+                    Equal => return (!self.take_and_promote(i).1).then_some(i),
                     Less => {
-                        std::mem::take(&mut self.waiting)
-                            .into_iter()
-                            .filter(|it| t != it)
-                            .for_each(|it| self.waiting.push(it));
+                        self.remove_waiting(t);
                         return None;
                     }
                     Greater => {}
                 }
             }
         }
+        // 保留已固定的键时，等待的键可能优先于所有固定的键
+        self.remove_waiting(t);
         None
     }
 
+    /// 移除所有不满足 `pred` 的键，包括等待队列中的键
+    ///
+    /// 固定的键被移除后，从等待队列中补充。
+    pub fn retain<P: FnMut(&T) -> bool>(&mut self, mut pred: P) {
+        self.waiting.retain(|t| pred(t));
+        let mut i = 0;
+        while i < self.pinned.len() {
+            match self.get(i) {
+                // 补充的键已移动到末尾，i 处换成了后一个键，需要再次检查
+                Some(t) if !pred(t) => {
+                    if !self.take_and_promote(i).1 {
+                        i += 1;
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    /// 一次移除 `keys` 中的所有键，包括等待队列中的键
+    ///
+    /// 结果与先移除等待的键、再按位置从后往前依次 [`remove`](Self::remove) 固定的键相同，
+    /// 但只在最后从等待队列中补充，且只标记最终改变的位置。
+    pub fn remove_all<'a, I>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        // 等待的键可能优先于固定的键，只能逐个补充
+        if !matches!(self.overflow, Overflow::EvictTail) {
+            for t in keys {
+                self.remove(t);
+            }
+            return;
+        }
+        let mut holes = Vec::new();
+        for t in keys {
+            match self.find(t) {
+                Some(i) if !holes.contains(&i) => holes.push(i),
+                Some(_) => {}
+                None => {
+                    self.remove_waiting(t);
+                }
+            }
+        }
+        holes.sort_unstable();
+        for &i in &holes {
+            self.log(IndexChange::Removed { index: i });
+        }
+        self.len -= holes.len();
+        // 靠后的空位由等待队列补充，其余空位保留
+        let fill = holes.len().min(self.waiting.len());
+        let (kept, filled) = holes.split_at(holes.len() - fill);
+        for &i in kept {
+            *self.get_mut(i) = None;
+            self.modified.clear(i);
+        }
+        let start = match filled.first() {
+            Some(&i) => i,
+            None => return,
+        };
+        // 补充的空位之后的位置依次前移，补充的键按优先级放在末尾
+        let mut region = Vec::with_capacity(self.pinned.len() - start);
+        for i in start..self.pinned.len() {
+            let t = self.get_mut(i).take();
+            if filled.binary_search(&i).is_err() {
+                region.push((Some(i), t));
+            }
+        }
+        for _ in 0..fill {
+            region.push((None, self.waiting.pop()));
+        }
+        for (i, (from, t)) in (start..).zip(region) {
+            match (from, &t) {
+                (Some(j), Some(_)) if j != i => {
+                    self.log(IndexChange::Moved { from: j, to: i });
+                    self.modified.set(i);
+                }
+                (Some(_), Some(_)) => {}
+                (None, Some(_)) => {
+                    self.log(IndexChange::Added { index: i });
+                    self.modified.clear(i);
+                }
+                _ => {
+                    self.modified.clear(i);
+                }
+            }
+            *self.get_mut(i) = t;
+        }
+        self.len += fill;
+    }
+
+    /// 缩小容量到 `new_capacity`，放不下的优先级最低的键进入等待队列
+    ///
+    /// 新容量不小于当前容量时无事发生。
+    pub fn shrink_to(&mut self, new_capacity: usize) {
+        if new_capacity >= self.pinned.len() {
+            return;
+        }
+        for i in new_capacity..self.pinned.len() {
+            if self.get(i).is_some() {
+                self.log(IndexChange::Removed { index: i });
+            }
+        }
+        let removed = self.pinned.split_off(new_capacity);
+        self.modified.truncate(new_capacity);
+        self.len = self.pinned.iter().flatten().count();
+        // 移出的键都不如留下的键，重新添加以填补留下的空位
+        for t in removed.into_iter().flatten() {
+            self.add(t);
+        }
+    }
+
+    /// 将所有固定的键依次移动到最前面，消除其间的空位
+    ///
+    /// 返回每次移动的原位置和新位置，移动后的位置被标记修改。
+    pub fn compact(&mut self) -> Vec<(usize, usize)> {
+        let mut moves = Vec::new();
+        let mut j = 0;
+        for i in 0..self.pinned.len() {
+            if self.get(i).is_some() {
+                if i != j {
+                    self.pinned.swap(i, j);
+                    self.modified.clear(i);
+                    self.modified.set(j);
+                    self.log(IndexChange::Moved { from: i, to: j });
+                    moves.push((i, j));
+                }
+                j += 1;
+            }
+        }
+        moves
+    }
+
+    /// 从等待队列中移除 `t`，返回是否找到
+    ///
+    /// 不影响已固定的键。
+    pub fn remove_waiting(&mut self, t: &T) -> bool {
+        let len = self.waiting.len();
+        let order = &self.order;
+        self.waiting.retain(|it| order.cmp(it, t) != Equal);
+        self.waiting.len() != len
+    }
+
     pub fn find(&self, t: &T) -> Option<usize> {
         for i in (0..self.pinned.len()).rev() {
             if let Some(ref item) = self.get(i) {
@@ -158,9 +554,245 @@ where
         None
     }
 
+    /// 按位置顺序产生所有落在 `range` 中的已固定键及其位置，跳过空位
+    ///
+    /// 范围的两端不必是已存在的键，两端本身也包含在范围中。
+    /// 已固定的键有序，只扫描到范围之外的第一个键为止。
+    pub fn find_range<'a>(
+        &'a self,
+        range: RangeInclusive<T>,
+    ) -> impl Iterator<Item = (usize, &'a T)> + 'a {
+        // 按位置顺序，先遇到更优先的一端
+        let (first, last) = if self.reversed {
+            (range.start(), range.end())
+        } else {
+            (range.end(), range.start())
+        };
+        let start = self
+            .pinned
+            .iter()
+            .position(|t| matches!(t, Some(t) if self.cmp(t, first) != Greater))
+            .unwrap_or(self.pinned.len());
+        let end = self.pinned[start..]
+            .iter()
+            .position(|t| matches!(t, Some(t) if self.cmp(t, last) == Less))
+            .map_or(self.pinned.len(), |i| start + i);
+        self.pinned[start..end]
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, item)| item.as_ref().map(|t| (start + i, t)))
+    }
+
+    /// 位于 `index` 的键在所有已固定键中的排名，即它之前的已固定键数
+    ///
+    /// `index` 处没有键时返回 `None`。
+    pub fn rank_of(&self, index: usize) -> Option<usize> {
+        self.pinned.get(index)?.as_ref()?;
+        Some(self.pinned[..index].iter().filter(|t| t.is_some()).count())
+    }
+
+    /// 排名为 `rank` 的已固定键及其位置，跳过空位
+    pub fn nth_occupied(&self, rank: usize) -> Option<(usize, &T)> {
+        self.pinned
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| item.as_ref().map(|t| (i, t)))
+            .nth(rank)
+    }
+
+    /// 各个位置是否有键，第 `i` 位对应位置 `i`，容量超过 64 时返回 `None`
+    pub fn occupancy(&self) -> Option<u64> {
+        if self.pinned.len() > u64::BITS as usize {
+            return None;
+        }
+        Some(
+            self.pinned
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| t.is_some())
+                .fold(0, |bits, (i, _)| bits | (1 << i)),
+        )
+    }
+
+    /// 各个位置是否有键，位置 `i` 对应第 `i / 8` 个字节的第 `i % 8` 位
+    pub fn occupancy_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.pinned.len().div_ceil(8)];
+        for (i, t) in self.pinned.iter().enumerate() {
+            if t.is_some() {
+                bytes[i / 8] |= 1 << (i % 8);
+            }
+        }
+        bytes
+    }
+
+    /// 按位置顺序复制所有已固定的键，跳过空位，不包括等待队列中的键
+    #[inline]
+    pub fn to_sorted_vec(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        self.pinned.iter().flatten().cloned().collect()
+    }
+
+    /// 最优先的已固定键及其位置，跳过空位
+    #[inline]
+    pub fn first(&self) -> Option<(usize, &T)> {
+        self.nth_occupied(0)
+    }
+
+    /// 最不优先的已固定键及其位置，跳过空位
+    pub fn last(&self) -> Option<(usize, &T)> {
+        self.pinned
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, item)| item.as_ref().map(|t| (i, t)))
+    }
+
+    /// 检查排序器的内部状态，用于调试
+    ///
+    /// 检查已固定的键按优先级排列、`len` 与已固定的键数一致、等待队列有序，
+    /// 以及策略为 [`Overflow::EvictTail`] 时等待的键都不优先于最后一个固定的键。
+    pub fn check(&self) -> Result<(), String> {
+        let occupied = self.pinned.iter().filter(|t| t.is_some()).count();
+        if occupied != self.len {
+            return Err(format!("len 为 {}，实际固定了 {occupied} 个键", self.len));
+        }
+        let mut prev: Option<(usize, &T)> = None;
+        for (i, t) in self.pinned.iter().enumerate() {
+            if let Some(t) = t {
+                if let Some((j, p)) = prev {
+                    if self.cmp(p, t) == Less {
+                        return Err(format!("位置 {j} 的键排在了更优先的位置 {i} 的键之前"));
+                    }
+                }
+                prev = Some((i, t));
+            }
+        }
+        if let Some(i) = (1..self.waiting.len())
+            .find(|&i| self.cmp(&self.waiting[i - 1], &self.waiting[i]) == Greater)
+        {
+            return Err(format!("等待队列的第 {i} 个键顺序错误"));
+        }
+        if let (Overflow::EvictTail, Some((j, tail))) = (&self.overflow, prev) {
+            if let Some(i) = self
+                .waiting
+                .iter()
+                .position(|t| self.cmp(t, tail) == Greater)
+            {
+                return Err(format!("等待队列的第 {i} 个键不应排在位置 {j} 的键之后"));
+            }
+        }
+        Ok(())
+    }
+
+    /// 按位置顺序产生所有已固定键的可变引用及其位置，跳过空位
+    ///
+    /// 只能修改不影响排序的部分，不会标记修改。排序依据改变时应该使用 [`reorder`](Self::reorder)。
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        self.pinned
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, item)| item.as_mut().map(|t| (i, t)))
+    }
+
     #[inline]
     pub fn update(&mut self, i: usize) -> bool {
-        unsafe { self.modified.clear(i) }
+        self.modified.clear(i)
+    }
+
+    /// 清空等待队列，按任意顺序产生其中的键
+    #[inline]
+    pub fn drain_waiting(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.waiting.drain(..)
+    }
+
+    /// 取出 `other` 中所有固定和等待的键，依次 [`add`](Self::add) 到此排序器
+    ///
+    /// 键按 `other` 中的优先级从高到低加入，放不下的键按此排序器的 [`Overflow`] 策略进入等待队列，
+    /// 与已有的键相等时按此排序器的 [`Tie`] 策略排列。
+    pub fn merge(&mut self, other: Indexer<T>) {
+        let pinned = other.pinned.into_iter().flatten();
+        let waiting = other.waiting.into_iter().rev();
+        for t in pinned.chain(waiting) {
+            self.add(t);
+        }
+    }
+
+    /// 将 `index` 处的键替换为 `new`，位置不变，返回原来的键
+    ///
+    /// `new` 必须仍然排在前后两个固定的键之间，并且优先于等待队列中的键，否则交还 `new`。
+    /// 需要移动位置时，可以先通过 [`iter_mut`](Self::iter_mut) 修改再 [`reorder`](Self::reorder)。
+    pub fn replace(&mut self, index: usize, new: T) -> Result<T, OutOfOrder<T>> {
+        if !matches!(self.pinned.get(index), Some(Some(_))) {
+            return Err(OutOfOrder(new));
+        }
+        let front = (0..index).rev().find(|&i| self.get(i).is_some());
+        let back = (index + 1..self.pinned.len()).find(|&i| self.get(i).is_some());
+        let fits = front.is_none_or(|i| self.cmp(&new, self.get_value(i)) == Less)
+            && back.is_none_or(|i| self.cmp(&new, self.get_value(i)) == Greater)
+            && !matches!(self.waiting.last(), Some(top) if self.cmp(top, &new) != Less);
+        if !fits {
+            return Err(OutOfOrder(new));
+        }
+        self.modified.set(index);
+        self.log(IndexChange::Removed { index });
+        self.log(IndexChange::Added { index });
+        Ok(self.get_mut(index).replace(new).unwrap())
+    }
+
+    /// 交换两个位置上的键并标记修改，空位随之交换
+    ///
+    /// 不检查排序，交换后的顺序可能违背排序依据，之后添加和移除键的结果也将随之错乱。
+    /// 仅用于需要强制指定个别键位置的特殊情况，可以用 [`check`](Self::check) 确认排序未被破坏。
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.pinned.swap(a, b);
+        if a != b {
+            self.log(IndexChange::Swapped { a, b });
+        }
+        for i in [a, b] {
+            if self.get(i).is_some() {
+                self.modified.set(i);
+            } else {
+                self.modified.clear(i);
+            }
+        }
+    }
+
+    /// 位于 `index` 的键的排序依据改变后，将其移动到正确的位置
+    ///
+    /// 返回键的新位置。如果键已不如等待队列中的键，它将进入等待队列并返回 `None`。
+    /// `index` 处没有键或超出容量时无事发生，返回 `None`。
+    pub fn reorder(&mut self, index: usize) -> Option<usize> {
+        let t = self.pinned.get_mut(index)?.take()?;
+        self.log(IndexChange::Removed { index });
+        self.modified.clear(index);
+        self.len -= 1;
+        // 键可能已不如等待队列中最优先的键
+        let demoted = matches!(self.waiting.last(), Some(top) if self.cmp(top, &t) == Greater);
+        let t = if demoted {
+            self.push_waiting(t);
+            self.waiting.pop().unwrap()
+        } else {
+            t
+        };
+        let i = self.add(t).unwrap();
+        if demoted {
+            None
+        } else {
+            if i != index {
+                self.modified.set(i);
+            }
+            Some(i)
+        }
+    }
+
+    /// 启用记录时记录位置的变化
+    #[inline]
+    fn log(&mut self, change: IndexChange) {
+        if let Some(changes) = &mut self.changes {
+            changes.push(change);
+        }
     }
 
     #[inline]
@@ -180,36 +812,78 @@ where
 
     #[inline]
     fn cmp(&self, a: &T, b: &T) -> Ordering {
+        let ord = self.order.cmp(a, b);
         if self.reversed {
-            use Ordering::*;
-            match a.cmp(b) {
-                Less => Greater,
-                Greater => Less,
-                Equal => Equal,
-            }
+            ord.reverse()
         } else {
-            a.cmp(b)
+            ord
         }
     }
 
-    /// 将 i 以 t 填充
-    #[inline]
-    fn remove_at(&mut self, i: usize) {
-        *self.get_mut(i) = None;
-        unsafe { self.modified.clear(i) };
+    /// 比较新键和已有的键，相等时按平局策略决定先后，不会返回 `Equal`
+    fn cmp_new(&self, new: &T, existing: &T) -> Ordering {
+        match self.cmp(new, existing) {
+            Equal => match &self.tie {
+                Tie::After => Less,
+                Tie::Before => Greater,
+                Tie::By(f) => match f(new, existing) {
+                    Equal => Less,
+                    ord if self.reversed => ord.reverse(),
+                    ord => ord,
+                },
+            },
+            ord => ord,
+        }
+    }
+
+    /// 将 t 按顺序放入等待队列，返回其位置
+    fn push_waiting(&mut self, t: T) -> usize {
+        let i = self
+            .waiting
+            .partition_point(|it| self.cmp(it, &t) != Greater);
+        self.waiting.insert(i, t);
+        i
+    }
+
+    /// 取出 i 处的键，从等待队列中补充一个键到末尾，返回取出的键和是否补充了键
+    fn take_and_promote(&mut self, i: usize) -> (T, bool) {
+        let t = self.get_mut(i).take().unwrap();
+        self.log(IndexChange::Removed { index: i });
+        self.modified.clear(i);
         self.len -= 1;
+        match self.waiting.pop() {
+            Some(next) if matches!(self.overflow, Overflow::EvictTail) => {
+                self.put_forward(i..self.pinned.len() - 1, next);
+                (t, true)
+            }
+            // 等待队列中的键可能优先于固定的键，按顺序插入
+            Some(next) => {
+                self.add(next);
+                (t, true)
+            }
+            None => (t, false),
+        }
     }
 
     /// 将 t 填充到 i 并移动到找到一个空位
     /// 不知道空位在何处
     fn put_somewhere_forward(&mut self, i: usize, mut t: T) {
-        t = std::mem::replace(self.get_mut(i).as_mut().unwrap(), t);
-        unsafe { self.modified.clear(i) };
+        if self.changes.is_some() {
+            // 前方第一个空位之后的键依次前移
+            if let Some(hole) = (0..i).rev().find(|&j| self.get(j).is_none()) {
+                for j in hole + 1..=i {
+                    self.log(IndexChange::Moved { from: j, to: j - 1 });
+                }
+            }
+            self.log(IndexChange::Added { index: i });
+        }
+        t = core::mem::replace(self.get_mut(i).as_mut().unwrap(), t);
+        self.modified.clear(i);
         self.len += 1;
         for i in (0..i).rev() {
-            unsafe { self.modified.set(i) };
+            self.modified.set(i);
             match self.get_mut(i) {
-                Some(t_) => t = std::mem::replace(t_, t),
+                Some(t_) => t = core::mem::replace(t_, t),
                 None => {
                     *self.get_mut(i) = Some(t);
                     return;
@@ -221,66 +895,109 @@ where
     /// 将空位以 t 填充并移动到范围另一端
     /// range 的开头（包括）是空位
     fn put_forward(&mut self, range: Range<usize>, t: T) {
+        if self.changes.is_some() {
+            for i in range.clone() {
+                self.log(IndexChange::Moved { from: i + 1, to: i });
+            }
+            self.log(IndexChange::Added { index: range.end });
+        }
         self.len += 1;
         *self.get_mut(range.start) = Some(t);
-        unsafe { self.modified.clear(range.end) };
+        self.modified.clear(range.end);
         for i in range {
             self.pinned.swap(i, i + 1);
-            unsafe { self.modified.set(i) };
+            self.modified.set(i);
         }
     }
 
     /// 将空位以 t 填充并移动到范围另一端
     /// range 的末尾（不包括）是空位
     fn put_backward(&mut self, range: Range<usize>, t: T) {
+        if self.changes.is_some() {
+            for i in range.clone().rev() {
+                self.log(IndexChange::Moved { from: i, to: i + 1 });
+            }
+            self.log(IndexChange::Added { index: range.start });
+        }
         self.len += 1;
         *self.get_mut(range.end) = Some(t);
-        unsafe { self.modified.clear(range.start) };
+        self.modified.clear(range.start);
         for i in range.rev() {
             self.pinned.swap(i, i + 1);
-            unsafe { self.modified.set(i + 1) };
+            self.modified.set(i + 1);
         }
     }
 }
 
-impl FlagVec {
+impl<T> Order<T> {
     #[inline]
-    fn with_capacity(capacity: usize) -> Self {
-        Self(vec![0; (capacity + 7) / 8])
+    fn cmp(&self, a: &T, b: &T) -> Ordering {
+        match self {
+            Self::Natural(f) => f(a, b),
+            Self::By(f) => f(a, b),
+        }
     }
+}
 
+impl<T> Overflow<T> {
+    /// 判断是否固定新键
     #[inline]
-    unsafe fn set(&mut self, i: usize) {
-        *self.0.get_unchecked_mut(i / 8) |= 1 << (i % 8);
+    fn accept(&self, new: &T, tail: &T) -> bool {
+        match self {
+            Self::EvictTail => true,
+            Self::RejectNew => false,
+            Self::Custom(f) => f(new, tail),
+        }
     }
+}
+
+/// 容量等于键的数量，所有键都被固定；没有键时容量为 0，之后添加的键都进入等待队列
+impl<T: Ord> FromIterator<T> for Indexer<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let items = iter.into_iter().collect::<Vec<_>>();
+        Self::from_iter_with_capacity(items.len(), items)
+    }
+}
 
-    unsafe fn clear(&mut self, i: usize) -> bool {
-        let block = self.0.get_unchecked_mut(i / 8);
-        let mask = 1 << (i % 8);
-        let result = (*block & mask) != 0;
-        *block &= !mask;
-        result
+impl<T: fmt::Debug> fmt::Debug for Indexer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 等待队列按优先级从高到低显示
+        let waiting = self.waiting.iter().rev().collect::<Vec<_>>();
+        f.debug_struct("Indexer")
+            .field("pinned", &self.pinned)
+            .field("waiting", &waiting)
+            .field("len", &self.len)
+            .field("reversed", &self.reversed)
+            .finish()
     }
 }
 
+/// 比较固定的键的布局和等待队列中的键，不比较修改标记
+impl<T: PartialEq> PartialEq for Indexer<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.reversed == other.reversed
+            && self.pinned == other.pinned
+            && self.waiting == other.waiting
+    }
+}
+
+impl<T: Eq> Eq for Indexer<T> {}
+
 #[cfg(test)]
 mod t {
     use super::*;
+    use alloc::vec;
 
     #[inline]
     fn vec_modified<T: Ord>(indexer: &Indexer<T>) -> Vec<bool> {
-        let mut result = vec![false; indexer.pinned.len()];
-        for (i, item) in result.iter_mut().enumerate().take(indexer.pinned.len()) {
-            if (indexer.modified.0[i / 8] & (1 << (i % 8))) != 0 {
-                *item = true;
-            }
-        }
-        result
+        (0..indexer.pinned.len())
+            .map(|i| indexer.modified.is_set(i))
+            .collect()
     }
 
     #[inline]
     fn vec_waiting<T: Ord + Copy>(indexer: &Indexer<T>) -> Vec<T> {
-        let mut x = indexer.waiting.iter().copied().collect::<Vec<_>>();
+        let mut x = indexer.waiting.to_vec();
         x.sort();
         x
     }
@@ -381,4 +1098,525 @@ mod t {
         assert_eq!(indexer.find(&1), Some(4));
         assert_eq!(indexer.find(&0), None);
     }
+
+    #[test]
+    fn reorder() {
+        let mut indexer = Indexer::from_iter_with_capacity(4, [8, 6, 4, 2, 0]);
+        for i in 0..4 {
+            indexer.update(i);
+        }
+        // 原地不动
+        indexer.pinned[1] = Some(7);
+        assert_eq!(indexer.reorder(1), Some(1));
+        assert_eq!(vec_modified(&indexer), vec![false; 4]);
+        // 向前移动
+        indexer.pinned[2] = Some(9);
+        assert_eq!(indexer.reorder(2), Some(0));
+        assert_eq!(indexer.pinned, vec![Some(9), Some(8), Some(7), Some(2)]);
+        assert_eq!(vec_modified(&indexer), vec![true, true, true, false]);
+        // 落入等待队列
+        indexer.pinned[0] = Some(-1);
+        assert_eq!(indexer.reorder(0), None);
+        assert_eq!(indexer.pinned, vec![Some(8), Some(7), Some(2), Some(0)]);
+        assert_eq!(vec_waiting(&indexer), vec![-1]);
+        assert_eq!(indexer.len(), 4);
+        // 位置为空
+        assert_eq!(Indexer::<i32>::new(2).reorder(0), None);
+        // 超出容量
+        assert_eq!(indexer.reorder(1000), None);
+        assert_eq!(indexer.pinned, vec![Some(8), Some(7), Some(2), Some(0)]);
+        // 反向排序
+        let mut indexer = Indexer::new_reversed(3);
+        for t in [1, 3, 5, 7] {
+            indexer.add(t);
+        }
+        indexer.pinned[2] = Some(0);
+        assert_eq!(indexer.reorder(2), Some(0));
+        assert_eq!(indexer.pinned, vec![Some(0), Some(1), Some(3)]);
+        indexer.pinned[0] = Some(8);
+        assert_eq!(indexer.reorder(0), None);
+        assert_eq!(indexer.pinned, vec![Some(1), Some(3), Some(7)]);
+        assert_eq!(vec_waiting(&indexer), vec![8]);
+    }
+
+    #[test]
+    fn drain_waiting() {
+        let mut indexer = Indexer::from_iter_with_capacity(2, 0..5);
+        let mut drained = indexer.drain_waiting().collect::<Vec<_>>();
+        drained.sort();
+        assert_eq!(drained, vec![0, 1, 2]);
+        assert_eq!(indexer.len(), 2);
+        assert_eq!(vec_waiting(&indexer), vec![]);
+        assert_eq!(indexer.pinned, vec![Some(4), Some(3)]);
+        // 等待队列清空后，移除的键不再被补充
+        assert_eq!(indexer.remove(&3), Some(1));
+        assert_eq!(indexer.pinned, vec![Some(4), None]);
+    }
+
+    #[test]
+    fn remove_waiting() {
+        let mut indexer = Indexer::from_iter_with_capacity(2, 0..5);
+        assert!(indexer.remove_waiting(&1));
+        assert!(!indexer.remove_waiting(&1));
+        // 已固定的键不受影响
+        assert!(!indexer.remove_waiting(&4));
+        assert_eq!(indexer.pinned, vec![Some(4), Some(3)]);
+        assert_eq!(vec_waiting(&indexer), vec![0, 2]);
+    }
+
+    #[test]
+    fn from_iter() {
+        let indexer = [3, 1, 2].into_iter().collect::<Indexer<i32>>();
+        assert_eq!(indexer.pinned, vec![Some(3), Some(2), Some(1)]);
+        assert_eq!(vec_waiting(&indexer), vec![]);
+        let indexer = Indexer::from_iter_with_capacity(2, [3, 1, 2]);
+        assert_eq!(indexer.pinned, vec![Some(3), Some(2)]);
+        assert_eq!(vec_waiting(&indexer), vec![1]);
+        // 空迭代器
+        let mut indexer = core::iter::empty().collect::<Indexer<i32>>();
+        assert_eq!(indexer.capacity(), 0);
+        assert_eq!(indexer.add(1), None);
+        assert_eq!(vec_waiting(&indexer), vec![1]);
+    }
+
+    #[test]
+    fn retain() {
+        let mut indexer = Indexer::from_iter_with_capacity(4, 0..8);
+        for i in 0..4 {
+            indexer.update(i);
+        }
+        // 移除奇数，从等待队列中补充偶数
+        indexer.retain(|t| t % 2 == 0);
+        assert_eq!(indexer.pinned, vec![Some(6), Some(4), Some(2), Some(0)]);
+        assert_eq!(vec_waiting(&indexer), vec![]);
+        assert_eq!(vec_modified(&indexer), vec![true, true, true, false]);
+        assert_eq!(indexer.len(), 4);
+        // 等待队列为空时留下空位
+        indexer.retain(|t| *t != 4);
+        assert_eq!(indexer.pinned, vec![Some(6), None, Some(2), Some(0)]);
+        assert_eq!(indexer.len(), 3);
+    }
+
+    #[test]
+    fn eq() {
+        let a = Indexer::from_iter_with_capacity(2, [1, 4, 2, 3]);
+        let b = Indexer::from_iter_with_capacity(2, [3, 2, 4, 1]);
+        assert_eq!(a, b);
+        assert_ne!(a, Indexer::from_iter_with_capacity(2, [1, 4, 3]));
+        assert_eq!(
+            alloc::format!("{a:?}"),
+            "Indexer { pinned: [Some(4), Some(3)], waiting: [2, 1], len: 2, reversed: false }"
+        );
+    }
+
+    #[test]
+    fn new_by() {
+        let mut indexer = Indexer::new_by(2, |t: &(u64, &str)| &t.0);
+        assert_eq!(indexer.add((1, "c")), Some(0));
+        assert_eq!(indexer.add((3, "a")), Some(0));
+        assert_eq!(indexer.add((2, "b")), Some(1));
+        assert_eq!(indexer.pinned, vec![Some((3, "a")), Some((2, "b"))]);
+        assert_eq!(indexer.find(&(2, "")), Some(1));
+        // 只比较取出的部分
+        assert_eq!(indexer.remove(&(3, "")), None);
+        assert_eq!(indexer.pinned, vec![Some((2, "b")), Some((1, "c"))]);
+    }
+
+    #[test]
+    fn shrink_to() {
+        let mut indexer = Indexer::<i32>::new(5);
+        for i in [9, 7, 5, 3, 1] {
+            indexer.add(i);
+        }
+        indexer.remove(&7);
+        for i in 0..5 {
+            indexer.update(i);
+        }
+        // 移出的键先填补空位
+        indexer.shrink_to(3);
+        assert_eq!(indexer.pinned, vec![Some(9), Some(5), Some(3)]);
+        assert_eq!(vec_waiting(&indexer), vec![1]);
+        assert_eq!(indexer.len(), 3);
+        indexer.shrink_to(4);
+        assert_eq!(indexer.pinned.len(), 3);
+        indexer.shrink_to(0);
+        assert_eq!(vec_waiting(&indexer), vec![1, 3, 5, 9]);
+        assert!(indexer.is_empty());
+        // 容量为 0 时新键只能等待
+        assert_eq!(indexer.add(7), None);
+        assert_eq!(vec_waiting(&indexer), vec![1, 3, 5, 7, 9]);
+        assert_eq!(indexer.probe(&11), InsertOutcome::Waiting);
+    }
+
+    #[test]
+    fn add_hint() {
+        let mut indexer = Indexer::<i32>::new(5);
+        let mut expected = Indexer::<i32>::new(5);
+        for (t, hint) in [(6, 0), (3, 1), (4, 1), (8, 4), (1, 0), (0, 3)] {
+            assert_eq!(indexer.add_hint(t, hint), expected.add(t));
+            assert_eq!(indexer, expected);
+            assert_eq!(vec_modified(&indexer), vec_modified(&expected));
+        }
+    }
+
+    #[test]
+    fn find_range() {
+        let mut indexer = Indexer::from_iter_with_capacity(6, [9, 7, 5, 3, 1]);
+        indexer.remove(&5);
+        assert_eq!(
+            indexer.find_range(2..=8).collect::<Vec<_>>(),
+            vec![(1, &7), (3, &3)]
+        );
+        assert_eq!(indexer.find_range(3..=3).collect::<Vec<_>>(), vec![(3, &3)]);
+        assert_eq!(indexer.find_range(10..=20).count(), 0);
+        assert_eq!(indexer.find_range(RangeInclusive::new(8, 2)).count(), 0);
+        // 反向排序
+        let mut indexer = Indexer::new_reversed(6);
+        for t in [9, 7, 5, 3, 1] {
+            indexer.add(t);
+        }
+        indexer.remove(&5);
+        assert_eq!(
+            indexer.find_range(2..=8).collect::<Vec<_>>(),
+            vec![(1, &3), (3, &7)]
+        );
+        assert_eq!(indexer.find_range(9..=9).collect::<Vec<_>>(), vec![(4, &9)]);
+        assert_eq!(indexer.find_range(-5..=0).count(), 0);
+    }
+
+    #[test]
+    fn rank_of() {
+        let mut indexer = Indexer::from_iter_with_capacity(6, [9, 7, 5, 3, 1]);
+        indexer.remove(&7);
+        assert_eq!(indexer.rank_of(0), Some(0));
+        assert_eq!(indexer.rank_of(1), None);
+        assert_eq!(indexer.rank_of(2), Some(1));
+        assert_eq!(indexer.rank_of(4), Some(3));
+        assert_eq!(indexer.rank_of(9), None);
+        assert_eq!(indexer.nth_occupied(1), Some((2, &5)));
+        assert_eq!(indexer.nth_occupied(3), Some((4, &1)));
+        assert_eq!(indexer.nth_occupied(4), None);
+    }
+
+    #[test]
+    fn iter_mut() {
+        let mut indexer = Indexer::new_by(4, |t: &(i32, i32)| &t.0);
+        indexer.add((3, 0));
+        indexer.add((1, 0));
+        indexer.add((2, 0));
+        indexer.remove(&(2, 0));
+        for (i, t) in indexer.iter_mut() {
+            t.1 = i as i32;
+        }
+        assert_eq!(indexer.pinned, vec![Some((3, 0)), None, Some((1, 2)), None]);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut indexer = Indexer::new(2).with_overflow(Overflow::RejectNew);
+        indexer.add(2);
+        indexer.add(1);
+        assert_eq!(indexer.add(3), None);
+        assert_eq!(indexer.pinned, vec![Some(2), Some(1)]);
+        // 移除优先于所有固定键的等待键
+        assert_eq!(indexer.remove(&3), None);
+        assert_eq!(vec_waiting(&indexer), vec![]);
+        assert_eq!(indexer.add(3), None);
+        // 相等的键也不挤出已固定的键
+        let mut tied = Indexer::new(1)
+            .with_overflow(Overflow::RejectNew)
+            .with_tie(Tie::Before);
+        tied.add(1);
+        assert_eq!(tied.add(1), None);
+        assert_eq!(tied.waiting_len(), 1);
+        // 出现空位时，等待的键按顺序插入
+        indexer.remove(&1);
+        assert_eq!(indexer.pinned, vec![Some(3), Some(2)]);
+
+        let mut indexer =
+            Indexer::new(2).with_overflow(Overflow::Custom(Box::new(|new, _| new % 2 == 0)));
+        indexer.add(2);
+        indexer.add(1);
+        assert_eq!(indexer.add(3), None);
+        assert_eq!(indexer.add(4), Some(0));
+        assert_eq!(indexer.pinned, vec![Some(4), Some(2)]);
+    }
+
+    #[test]
+    fn compact() {
+        let mut indexer = Indexer::from_iter_with_capacity(5, [9, 7, 5, 3]);
+        indexer.remove(&9);
+        indexer.remove(&5);
+        assert_eq!(indexer.compact(), vec![(1, 0), (3, 1)]);
+        assert_eq!(indexer.pinned, vec![Some(7), Some(3), None, None, None]);
+        assert_eq!(
+            vec_modified(&indexer),
+            vec![true, true, false, false, false]
+        );
+        assert_eq!(indexer.compact(), vec![]);
+    }
+
+    #[test]
+    fn remove_all() {
+        // 等待队列足够补充所有空位
+        let mut a = Indexer::from_iter_with_capacity(4, [9, 8, 7, 6, 5, 4]);
+        let mut b = Indexer::from_iter_with_capacity(4, [9, 8, 7, 6, 5, 4]);
+        a.remove_all(&[8, 6, 4]);
+        b.remove(&4);
+        b.remove(&6);
+        b.remove(&8);
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 3);
+        // 等待队列不足以补充所有空位
+        let mut a = Indexer::from_iter_with_capacity(4, [9, 8, 7, 6, 5]);
+        let mut b = Indexer::from_iter_with_capacity(4, [9, 8, 7, 6, 5]);
+        a.remove_all(&[7, 9]);
+        b.remove(&7);
+        b.remove(&9);
+        assert_eq!(a, b);
+        assert_eq!(a.pinned, vec![None, Some(8), Some(6), Some(5)]);
+        assert_eq!(a.len(), 3);
+        assert_eq!(vec_modified(&a), vec![false, false, true, false]);
+    }
+
+    #[test]
+    fn add_reporting() {
+        let mut indexer = Indexer::from_iter_with_capacity(2, [5, 3]);
+        let result = indexer.add_reporting(1);
+        assert_eq!(result.index, None);
+        assert_eq!(result.displaced, None);
+        let result = indexer.add_reporting(4);
+        assert_eq!(result.index, Some(1));
+        assert_eq!(result.displaced, Some(&3));
+        assert_eq!(vec_waiting(&indexer), vec![1, 3]);
+    }
+
+    #[test]
+    fn replace() {
+        let mut indexer = Indexer::from_iter_with_capacity(3, [9, 5, 1, 0]);
+        assert_eq!(indexer.replace(1, 6), Ok(5));
+        assert_eq!(indexer.pinned, vec![Some(9), Some(6), Some(1)]);
+        assert_eq!(vec_modified(&indexer), vec![false, true, false]);
+        assert_eq!(indexer.replace(1, 10), Err(OutOfOrder(10)));
+        assert_eq!(indexer.replace(2, 0), Err(OutOfOrder(0)));
+        assert_eq!(indexer.replace(2, 2), Ok(1));
+        indexer.remove(&6);
+        assert_eq!(indexer.replace(1, 0), Err(OutOfOrder(0)));
+        // 反向排序
+        let mut indexer = Indexer::new_reversed(3);
+        for t in [1, 3, 5, 10] {
+            indexer.add(t);
+        }
+        assert_eq!(indexer.replace(1, 4), Ok(3));
+        assert_eq!(indexer.replace(2, 11), Err(OutOfOrder(11)));
+        assert_eq!(indexer.pinned, vec![Some(1), Some(4), Some(5)]);
+    }
+
+    #[test]
+    fn first_last() {
+        let mut indexer = Indexer::from_iter_with_capacity(4, [9, 5, 1]);
+        assert_eq!(indexer.first(), Some((0, &9)));
+        assert_eq!(indexer.last(), Some((2, &1)));
+        indexer.remove(&9);
+        indexer.remove(&1);
+        assert_eq!(indexer.first(), Some((1, &5)));
+        assert_eq!(indexer.last(), Some((1, &5)));
+        indexer.remove(&5);
+        assert_eq!(indexer.first(), None);
+        assert_eq!(indexer.last(), None);
+    }
+
+    #[test]
+    fn to_sorted_vec() {
+        let mut indexer = Indexer::from_iter_with_capacity(3, [9, 5, 1, 0]);
+        indexer.remove(&5);
+        indexer.remove(&0);
+        assert_eq!(indexer.to_sorted_vec(), vec![9, 1]);
+    }
+
+    #[test]
+    fn occupancy() {
+        let mut indexer = Indexer::from_iter_with_capacity(10, [9, 8, 7, 6, 5, 4, 3, 2, 1]);
+        indexer.remove(&8);
+        indexer.remove(&1);
+        assert_eq!(indexer.occupancy(), Some(0b0_1111_1101));
+        assert_eq!(indexer.occupancy_bytes(), vec![0b1111_1101, 0b0]);
+        assert_eq!(Indexer::<i32>::new(65).occupancy(), None);
+    }
+
+    #[test]
+    fn probe() {
+        // 与 add 的结果一致
+        let build = || {
+            let mut indexer = Indexer::from_iter_with_capacity(5, [9, 7, 5, 3]);
+            indexer.remove(&7);
+            indexer
+        };
+        for t in [10, 8, 6, 4, 2] {
+            let mut indexer = build();
+            let index = indexer.add(t).unwrap();
+            assert_eq!(build().probe(&t), InsertOutcome::Index(index));
+        }
+        let mut indexer = Indexer::from_iter_with_capacity(3, [9, 7, 5]);
+        assert_eq!(indexer.probe(&1), InsertOutcome::Waiting);
+        assert_eq!(
+            indexer.probe(&8),
+            InsertOutcome::Displaces {
+                index: 1,
+                displaced: &5
+            }
+        );
+        assert_eq!(indexer.add(8), Some(1));
+        let indexer = indexer.with_overflow(Overflow::RejectNew);
+        assert_eq!(indexer.probe(&10), InsertOutcome::Waiting);
+    }
+
+    #[test]
+    fn check() {
+        let mut indexer = Indexer::new(4);
+        for t in [3, 8, 1, 6, 9, 2, 7] {
+            indexer.add(t);
+            assert_eq!(indexer.check(), Ok(()));
+        }
+        indexer.remove(&8);
+        indexer.remove_all(&[9, 3]);
+        assert_eq!(indexer.check(), Ok(()));
+        indexer.len += 1;
+        assert!(indexer.check().is_err());
+        indexer.len -= 1;
+        indexer.pinned.swap(0, 1);
+        assert!(indexer.check().is_err());
+        indexer.pinned.swap(0, 1);
+        indexer.waiting.push(10);
+        assert!(indexer.check().is_err());
+        // 反向排序时补充最优先的等待键
+        let mut indexer = Indexer::new_reversed(3);
+        for t in [1, 2, 3, 10, 5] {
+            indexer.add(t);
+        }
+        indexer.remove(&1);
+        assert_eq!(indexer.pinned, vec![Some(2), Some(3), Some(5)]);
+        assert_eq!(indexer.check(), Ok(()));
+    }
+
+    #[test]
+    fn tie() {
+        fn key(t: &(i32, char)) -> &i32 {
+            &t.0
+        }
+        let mut indexer = Indexer::new_by(4, key);
+        indexer.add((5, 'a'));
+        assert_eq!(indexer.add((5, 'b')), Some(1));
+        assert_eq!(indexer.check(), Ok(()));
+
+        let mut indexer = Indexer::new_by(4, key).with_tie(Tie::Before);
+        indexer.add((5, 'a'));
+        indexer.add((5, 'b'));
+        assert_eq!(indexer.first(), Some((0, &(5, 'b'))));
+
+        let mut indexer = Indexer::new_by(2, key).with_tie(Tie::By(Box::new(|a, b| a.1.cmp(&b.1))));
+        indexer.add((5, 'b'));
+        indexer.add((5, 'a'));
+        assert_eq!(indexer.first(), Some((0, &(5, 'b'))));
+        // 已满时按平局策略决定是否等待
+        assert_eq!(indexer.add((5, 'c')), Some(0));
+        assert_eq!(indexer.add((5, '0')), None);
+        assert_eq!(indexer.check(), Ok(()));
+    }
+
+    #[test]
+    fn merge() {
+        let mut indexer = Indexer::from_iter_with_capacity(4, [9, 5, 1]);
+        indexer.remove(&5);
+        let other = Indexer::from_iter_with_capacity(2, [8, 6, 4, 2]);
+        indexer.merge(other);
+        assert_eq!(indexer.to_sorted_vec(), vec![9, 8, 6, 4]);
+        assert_eq!(vec_waiting(&indexer), vec![1, 2]);
+        assert_eq!(indexer.check(), Ok(()));
+    }
+
+    #[test]
+    fn swap() {
+        let mut indexer = Indexer::from_iter_with_capacity(4, [9, 7, 5]);
+        indexer.remove(&7);
+        indexer.swap(0, 1);
+        assert_eq!(indexer.find(&9), Some(1));
+        assert_eq!(vec_modified(&indexer), vec![false, true, false, false]);
+        assert_eq!(indexer.check(), Ok(()));
+        indexer.swap(1, 2);
+        assert!(indexer.check().is_err());
+    }
+
+    #[test]
+    fn stats() {
+        let mut indexer = Indexer::from_iter_with_capacity(4, [9, 7, 5, 3, 1]);
+        assert_eq!(indexer.capacity(), 4);
+        assert_eq!(indexer.waiting_len(), 1);
+        assert_eq!(indexer.occupancy_ratio(), 1.0);
+        indexer.drain_waiting().for_each(drop);
+        indexer.remove(&9);
+        assert_eq!(indexer.occupancy_ratio(), 0.75);
+        assert_eq!(Indexer::<i32>::new(0).occupancy_ratio(), 1.0);
+    }
+
+    /// 将记录的变化应用到镜像上，检查与排序器的布局一致
+    fn replay(indexer: &mut Indexer<i32>, mirror: &mut Vec<Option<i32>>) {
+        for c in indexer.drain_changes() {
+            match c {
+                IndexChange::Added { index } => {
+                    assert_eq!(mirror[index], None, "{c:?}");
+                    mirror[index] = indexer.pinned[index];
+                }
+                IndexChange::Removed { index } => {
+                    assert!(mirror[index].take().is_some(), "{c:?}");
+                }
+                IndexChange::Moved { from, to } => {
+                    assert_eq!(mirror[to], None, "{c:?}");
+                    mirror[to] = mirror[from].take();
+                    assert!(mirror[to].is_some(), "{c:?}");
+                }
+                IndexChange::Swapped { a, b } => mirror.swap(a, b),
+            }
+        }
+        mirror.truncate(indexer.pinned.len());
+        assert_eq!(*mirror, indexer.pinned);
+    }
+
+    #[test]
+    fn test_change_log() {
+        let mut indexer = Indexer::new(5).with_change_log();
+        let mut mirror = vec![None; 5];
+        for t in [5, 1, 3, 9, 7, 4, 2, 8] {
+            indexer.add(t);
+            replay(&mut indexer, &mut mirror);
+        }
+        indexer.remove(&3);
+        replay(&mut indexer, &mut mirror);
+        indexer.remove_all(&[1, 9, 5]);
+        replay(&mut indexer, &mut mirror);
+        indexer.add(6);
+        indexer.add_hint(0, 0);
+        replay(&mut indexer, &mut mirror);
+        indexer.swap(0, 4);
+        indexer.swap(0, 4);
+        replay(&mut indexer, &mut mirror);
+        let i = indexer.find(&6).unwrap();
+        indexer
+            .iter_mut()
+            .find(|(j, _)| *j == i)
+            .unwrap()
+            .1
+            .clone_from(&-1);
+        indexer.reorder(i);
+        replay(&mut indexer, &mut mirror);
+        indexer.remove(&-1);
+        indexer.compact();
+        replay(&mut indexer, &mut mirror);
+        indexer.shrink_to(2);
+        replay(&mut indexer, &mut mirror);
+        // 未启用时不记录
+        let mut indexer = Indexer::new(2);
+        indexer.add(1);
+        assert!(indexer.drain_changes().is_empty());
+    }
 }
@@ -0,0 +1,134 @@
+use super::Indexer;
+use core::cmp::Ordering;
+
+/// 依据键排序并为每个键保存一个值的排序器
+///
+/// 值随键一起移动，不需要另外维护按位置索引的数组。
+pub struct IndexerMap<K, V>(Indexer<Entry<K, V>>);
+
+/// 只依据键比较的键值对
+struct Entry<K, V>(K, V);
+
+impl<K: Ord, V> PartialEq for Entry<K, V> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<K: Ord, V> Eq for Entry<K, V> {}
+
+impl<K: Ord, V> PartialOrd for Entry<K, V> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, V> Ord for Entry<K, V> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl<K: Ord, V> IndexerMap<K, V> {
+    #[inline]
+    pub fn new(capacity: usize) -> Self {
+        Self(Indexer::new(capacity))
+    }
+
+    #[inline]
+    pub fn new_reversed(capacity: usize) -> Self {
+        Self(Indexer::new_reversed(capacity))
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.0.is_full()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// 添加键值对，返回键的位置，键进入等待队列时返回 `None`
+    #[inline]
+    pub fn add(&mut self, k: K, v: V) -> Option<usize> {
+        self.0.add(Entry(k, v))
+    }
+
+    /// 移除键并返回其值，无论键已固定还是在等待队列中
+    ///
+    /// 固定的键被移除后，从等待队列中补充。
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        match self.find(k) {
+            Some(i) => {
+                let (e, _) = self.0.take_and_promote(i);
+                Some(e.1)
+            }
+            None => {
+                let waiting = &mut self.0.waiting;
+                let i = waiting.iter().position(|e| e.0 == *k)?;
+                Some(waiting.remove(i).1)
+            }
+        }
+    }
+
+    /// 找到已固定的键的位置
+    #[inline]
+    pub fn find(&self, k: &K) -> Option<usize> {
+        self.0
+            .pinned
+            .iter()
+            .position(|e| matches!(e, Some(e) if e.0 == *k))
+    }
+
+    /// 获取位于 `i` 的键值对
+    #[inline]
+    pub fn get(&self, i: usize) -> Option<(&K, &V)> {
+        self.0.pinned.get(i)?.as_ref().map(|e| (&e.0, &e.1))
+    }
+
+    /// 获取位于 `i` 的键和值的可变引用
+    #[inline]
+    pub fn get_mut(&mut self, i: usize) -> Option<(&K, &mut V)> {
+        self.0.pinned.get_mut(i)?.as_mut().map(|e| (&e.0, &mut e.1))
+    }
+
+    #[inline]
+    pub fn update(&mut self, i: usize) -> bool {
+        self.0.update(i)
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let mut map = IndexerMap::new(2);
+        assert_eq!(map.add(1, 'a'), Some(0));
+        assert_eq!(map.add(3, 'c'), Some(0));
+        assert_eq!(map.add(2, 'b'), Some(1));
+        assert_eq!(map.get(0), Some((&3, &'c')));
+        assert_eq!(map.get(1), Some((&2, &'b')));
+        // 值随键移动
+        *map.get_mut(1).unwrap().1 = 'B';
+        assert_eq!(map.remove(&3), Some('c'));
+        assert_eq!(map.get(0), Some((&2, &'B')));
+        assert_eq!(map.get(1), Some((&1, &'a')));
+        // 从等待队列移除
+        map.add(4, 'd');
+        assert_eq!(map.remove(&1), Some('a'));
+        assert_eq!(map.remove(&1), None);
+        assert_eq!(map.len(), 2);
+    }
+}
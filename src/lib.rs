@@ -1,18 +1,72 @@
-use async_std::task;
-use std::{
-    sync::Arc,
-    time::{Duration, Instant},
-};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
+mod dirty_bits;
 mod indexer;
+
+pub use dirty_bits::DirtyBits;
+pub use indexer::{
+    AddResult, IndexChange, Indexer, IndexerMap, InsertOutcome, OutOfOrder, Overflow, Tie,
+};
+
+#[cfg(feature = "std")]
+mod bus;
+#[cfg(feature = "std")]
+mod capabilities;
+#[cfg(feature = "std")]
+mod either;
+#[cfg(feature = "std")]
+mod metrics;
+#[cfg(feature = "std")]
+mod pacemaker;
+#[cfg(feature = "std")]
+mod pause;
+#[cfg(feature = "std")]
+mod rate;
+#[cfg(feature = "std")]
+mod stop;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "std")]
+mod supervisor;
+#[cfg(feature = "std")]
 mod supervisor_multiple;
+#[cfg(feature = "std")]
 mod supervisor_single;
+#[cfg(feature = "std")]
+mod trace;
 
-pub use indexer::Indexer;
-pub use supervisor_multiple::{
-    MultipleDeviceDriver, SupervisorEventForMultiple, SupervisorForMultiple,
+#[cfg(feature = "std")]
+pub use {
+    bus::{Bus, BusDriver, BusEvent},
+    capabilities::Capabilities,
+    either::{Either, EitherDriver, EitherPacemaker},
+    metrics::Metrics,
+    pacemaker::PacemakerHandle,
+    pause::Pause,
+    rate::RateMonitor,
+    stop::Stop,
+    stream::{StreamDevice, StreamDriver},
+    supervisor::Supervisor,
+    supervisor_multiple::{
+        CommandNotify, CommandRouter, CommandSender, DisconnectReason, JoinReport,
+        MultipleDeviceDriver, NotConnected, Replier, Reply, SupervisorEventForMultiple,
+        SupervisorForMultiple,
+    },
+    supervisor_single::{StepResult, SupervisorEventForSingle, SupervisorForSingle},
+};
+
+#[cfg(feature = "std")]
+use {
+    async_std::{channel, future, task},
+    std::{
+        io,
+        sync::Arc,
+        time::{Duration, Instant},
+    },
+    trace::{debug, trace},
 };
-pub use supervisor_single::{SupervisorEventForSingle, SupervisorForSingle};
 
 /// 实现驱动特性，需要指定其对应的起搏器类型、状态类型和指令类型。
 ///
@@ -21,16 +75,71 @@ pub use supervisor_single::{SupervisorEventForSingle, SupervisorForSingle};
 /// 可以从驱动中读取状态，或向驱动发送指令。
 ///
 /// 监听驱动事件是独占且阻塞的，但在传入的回调中可以向其中发送指令。
+#[cfg(feature = "std")]
 pub trait Driver: 'static + Send + Sized {
     type Pacemaker: DriverPacemaker + Send;
     type Key;
     type Event;
 
+    /// 驱动声明支持的功能
+    const CAPABILITIES: Capabilities = Capabilities::NONE;
+
+    /// 所有可能的键，按优先级从高到低排列
+    ///
+    /// 打开驱动时按此顺序取舍：通过验证的设备多于所需时，保留靠前的设备。
     fn keys() -> Vec<Self::Key>;
     fn open_timeout() -> Duration;
 
+    /// 所有看起来存在设备的键，不打开设备，默认为 [`keys`](Self::keys)
+    ///
+    /// 可以只检查端口是否存在等廉价的条件。打开驱动时只尝试这些键。
+    #[inline]
+    fn scan() -> Vec<Self::Key> {
+        Self::keys()
+    }
+
+    /// 打开驱动时寻找设备的总时限，默认为 [`open_timeout`](Self::open_timeout)
+    ///
+    /// 超过时限后不再打开新的设备，正在验证的设备也将结束验证。
+    #[inline]
+    fn discover_timeout() -> Duration {
+        Self::open_timeout()
+    }
+
+    /// 打开的设备证明自己的时限，默认为 [`open_timeout`](Self::open_timeout)
+    ///
+    /// 每批设备打开后开始计时，不超过寻找设备的总时限。
+    #[inline]
+    fn validate_timeout() -> Duration {
+        Self::open_timeout()
+    }
+
+    /// 验证时限为 0 时是否仍然验证设备
+    ///
+    /// 返回 `true` 时，设备只需产生一次事件即通过验证；否则打开后不经验证直接返回。
+    #[inline]
+    fn validate_without_timeout() -> bool {
+        false
+    }
+
+    /// 打开的设备在验证时限内至少需要产生的事件数，用于筛除只产生一次偶然输出的设备
+    ///
+    /// 对所有打开驱动的方法生效，使用 [`open_some_validated`](Self::open_some_validated) 时只计通过检查的事件。
+    #[inline]
+    fn min_events() -> usize {
+        0
+    }
+
     fn new(t: &Self::Key) -> Option<(Self::Pacemaker, Self)>;
 
+    /// 打开一个设备的时限，默认不限时
+    ///
+    /// 限时时 [`new`](Self::new) 在其他线程中调用，超时的设备被舍弃，不影响寻找其他设备。
+    #[inline]
+    fn new_timeout() -> Duration {
+        Duration::MAX
+    }
+
     /// 阻塞等待驱动退出
     ///
     /// 驱动可能因为两种原因退出：
@@ -43,73 +152,508 @@ pub trait Driver: 'static + Send + Sized {
     where
         F: FnMut(&mut Self, Option<(Instant, Self::Event)>) -> bool;
 
-    fn open_some(len: usize) -> Vec<(Self::Key, Box<Self>)> {
-        // 打开所有可能的驱动并启动起搏器
-        // 这段的耗时不计入超时
-        let drivers: Vec<_> = Self::keys()
+    /// 判断事件是否需要交给监控器，返回 `false` 的事件在驱动线程中直接丢弃
+    ///
+    /// 被丢弃的事件不会刷新监控器的存活计时。
+    #[inline]
+    fn filter_event(&self, _e: &Self::Event) -> bool {
+        true
+    }
+
+    /// 打开的设备未被采用时调用，可以用于诊断设备或线缆的问题
+    ///
+    /// 调用前设备的起搏器已经停止。
+    #[inline]
+    fn rejected(_t: &Self::Key, _reason: RejectReason) {}
+
+    /// 设备的唯一标识，同一个设备出现在多个键下时用于去重
+    ///
+    /// 标识相同的设备只保留优先级最高的一个，返回 `None` 的设备不参与去重。
+    #[inline]
+    fn identity(&self) -> Option<String> {
+        None
+    }
+
+    /// 日志中显示的键，默认为 `None`，只以键的散列值区分设备
+    ///
+    /// 需要将日志对应到具体的端口或路径时，可以返回 `Some(format!("{t:?}"))`。只在启用 `tracing` 特性时调用。
+    #[inline]
+    fn key_label(_t: &Self::Key) -> Option<String> {
+        None
+    }
+
+    /// 打开的设备被采用时调用，可以用于统计设备的初始化耗时
+    ///
+    /// `latency` 是从打开设备到其通过验证的耗时，未经验证或未产生事件即被采用时为 `None`。
+    #[inline]
+    fn accepted(_t: &Self::Key, _latency: Option<Duration>) {}
+
+    /// 监控器丢弃驱动对象前调用，可以在此停止设备输出、清空缓冲区或复位设备
+    ///
+    /// 交给调用者的驱动对象不会调用此方法。
+    #[inline]
+    fn shutdown(&mut self) {}
+
+    /// 驱动因错误退出后，尝试不关闭设备而原地恢复，例如重新握手或清空缓冲区
+    ///
+    /// 返回 `true` 表示已恢复，监控器将继续监听此驱动；复位后未产生事件就再次退出时，监控器不再复位而是断连。
+    #[inline]
+    fn reset(&mut self) -> bool {
+        false
+    }
+
+    /// 打开至多 `len` 个驱动，起搏器在后台运行
+    ///
+    /// 返回的驱动按 [`keys`](Self::keys) 的顺序排列。
+    fn open_some(len: usize) -> Vec<(Self::Key, Box<Self>)>
+    where
+        Self::Key: Send + 'static,
+    {
+        Self::open_some_with_pacemaker(len)
+            .into_iter()
+            .map(|(t, d, _)| (t, d))
+            .collect()
+    }
+
+    /// 只从 `keys` 中打开至多 `len` 个驱动，不枚举其他键
+    ///
+    /// 与 [`open_some`](Self::open_some) 同样经过验证，返回的驱动按 `keys` 的顺序排列。
+    fn open_some_from<I>(keys: I, len: usize) -> Vec<(Self::Key, Box<Self>)>
+    where
+        I: IntoIterator<Item = Self::Key>,
+        Self::Key: Send + 'static,
+    {
+        open_keys(
+            keys.into_iter().collect(),
+            len,
+            0,
+            |_, _| true,
+            &Stop::default(),
+            None,
+        )
+        .into_iter()
+        .map(|(t, d, _)| (t, d))
+        .collect()
+    }
+
+    /// 打开至多 `len` 个驱动，同时返回控制其起搏器的句柄
+    #[allow(clippy::type_complexity)]
+    fn open_some_with_pacemaker(
+        len: usize,
+    ) -> Vec<(Self::Key, Box<Self>, PacemakerHandle<Self::Pacemaker>)>
+    where
+        Self::Key: Send + 'static,
+    {
+        Self::open_some_validated(len, 0, |_, _| true)
+    }
+
+    /// 打开至多 `len` 个驱动，只保留验证通过的驱动
+    ///
+    /// 每个设备使用 `validate` 的一个副本检查其事件，在超时前至少 `min_valid` 个事件通过检查的设备才会被保留。
+    /// [`min_events`](Self::min_events) 更大时以它为准。
+    /// `min_valid` 不为 0 时，即使验证时限为 0 也会验证设备产生的第一个事件。
+    #[allow(clippy::type_complexity)]
+    fn open_some_validated<V>(
+        len: usize,
+        min_valid: usize,
+        validate: V,
+    ) -> Vec<(Self::Key, Box<Self>, PacemakerHandle<Self::Pacemaker>)>
+    where
+        V: FnMut(&mut Self, &Self::Event) -> bool + Clone + Send + 'static,
+        Self::Key: Send + 'static,
+    {
+        open_keys(
+            Self::scan(),
+            len,
+            min_valid,
+            validate,
+            &Stop::default(),
+            None,
+        )
+    }
+
+    /// 与 [`open_some_with_pacemaker`](Self::open_some_with_pacemaker) 相同，`cancel` 停止后尽快结束
+    ///
+    /// 取消后不再打开新的设备，正在验证的设备立即结束验证并被关闭，不经验证直接采用的设备仍然返回。
+    #[allow(clippy::type_complexity)]
+    fn open_some_cancellable(
+        len: usize,
+        cancel: &Stop,
+    ) -> Vec<(Self::Key, Box<Self>, PacemakerHandle<Self::Pacemaker>)>
+    where
+        Self::Key: Send + 'static,
+    {
+        open_keys(Self::scan(), len, 0, |_, _| true, cancel, None)
+    }
+
+    /// 只打开 `key` 对应的驱动，不枚举其他键
+    ///
+    /// 与 [`open_some`](Self::open_some) 同样经过验证。
+    #[inline]
+    fn open_one(key: &Self::Key) -> Option<Box<Self>>
+    where
+        Self::Key: Clone + Send + 'static,
+    {
+        Self::open_one_with_pacemaker(key).map(|(d, _)| d)
+    }
+
+    /// 只打开 `key` 对应的驱动，同时返回控制其起搏器的句柄
+    #[inline]
+    fn open_one_with_pacemaker(
+        key: &Self::Key,
+    ) -> Option<(Box<Self>, PacemakerHandle<Self::Pacemaker>)>
+    where
+        Self::Key: Clone + Send + 'static,
+    {
+        open_keys(vec![key.clone()], 1, 0, |_, _| true, &Stop::default(), None)
+            .pop()
+            .map(|(_, d, p)| (d, p))
+    }
+
+    /// 打开驱动时最多同时验证的设备数
+    ///
+    /// 候选设备较多时，可以限制同时打开的设备数，分批验证直到找到足够的设备。
+    #[inline]
+    fn max_probes() -> usize {
+        usize::MAX
+    }
+}
+
+/// 从 `keys` 中打开至多 `len` 个驱动，只保留验证通过的驱动
+///
+/// `cancel` 停止后尽快结束；指定了 `deadline` 时，寻找设备的时限不超过 `deadline`。
+#[cfg(feature = "std")]
+#[allow(clippy::type_complexity)]
+fn open_keys<D, V>(
+    keys: Vec<D::Key>,
+    len: usize,
+    min_valid: usize,
+    validate: V,
+    cancel: &Stop,
+    deadline: Option<Instant>,
+) -> Vec<(D::Key, Box<D>, PacemakerHandle<D::Pacemaker>)>
+where
+    D: Driver,
+    D::Key: Send + 'static,
+    V: FnMut(&mut D, &D::Event) -> bool + Clone + Send + 'static,
+{
+    #[cfg(feature = "tracing")]
+    let (_span, start) = (
+        tracing::debug_span!("open_some", len).entered(),
+        Instant::now(),
+    );
+    debug!(len, candidates = keys.len(), "probing start");
+    let min_valid = min_valid.max(D::min_events());
+    // 如果验证时限为 0，只验证一次或直接退出
+    let validate_timeout = D::validate_timeout();
+    let once = validate_timeout == Duration::ZERO;
+    if once && !D::validate_without_timeout() && min_valid == 0 {
+        let mut identities = Vec::new();
+        return keys
             .into_iter()
+            .take_while(|_| !cancel.is_stopped() && deadline.is_none_or(|t| Instant::now() < t))
             .filter_map(|t| {
-                Self::new(&t).map(|(mut p, d)| {
-                    task::spawn(async move {
-                        let period = Self::Pacemaker::period();
-                        while p.send() {
-                            task::sleep(period).await;
-                        }
-                    });
-                    (t, Box::new(d))
-                })
+                let (t, p, mut d) = new_driver::<D>(t)?;
+                if let Some(id) = d.identity() {
+                    if identities.contains(&id) {
+                        d.shutdown();
+                        D::rejected(&t, RejectReason::Duplicate);
+                        return None;
+                    }
+                    identities.push(id);
+                }
+                D::accepted(&t, None);
+                Some((t, Box::new(d), PacemakerHandle::spawn(p)))
             })
+            .take(len)
             .collect();
-        // 如果超时为 0，直接退出
-        let open_timeout = Self::open_timeout();
-        let deadline = if open_timeout != Duration::ZERO {
-            Instant::now() + open_timeout
-        } else {
-            return drivers;
-        };
-        // 打开临时的监控以筛除不产生正确输出的设备
-        let counter = Arc::new(()); // ---------------------- // 用一个 Arc 来计数
-        #[allow(clippy::needless_collect)]
-        let drivers = drivers
-            .into_iter()
-            .map(|(t, mut d)| {
-                let counter = counter.clone();
-                (
-                    t,
-                    task::spawn_blocking(move || {
-                        let ok = d.join(|_, _| {
-                            Arc::strong_count(&counter) > len && Instant::now() < deadline
-                        });
-                        if ok {
-                            Some(d)
-                        } else {
-                            None
-                        }
-                    }),
-                )
-            })
+    }
+    // 分批打开驱动并验证，直到找到足够的设备或超时
+    let mut keys = keys.into_iter();
+    let mut discover_deadline = None;
+    let mut drivers = Vec::new();
+    while drivers.len() < len && !cancel.is_stopped() {
+        let wave = keys
+            .by_ref()
+            .take(D::max_probes().max(1))
             .collect::<Vec<_>>();
-        std::mem::drop(counter); // 丢弃外面的引用，此后引用计数 === 存活的驱动数
-                                 // 收集正确打开的驱动
-        drivers
+        if wave.is_empty() {
+            break;
+        }
+        // 打开这一批驱动并启动起搏器
+        // 第一批的耗时不计入超时
+        let wave = wave
             .into_iter()
-            .filter_map(|(t, o)| task::block_on(o).map(|b| (t, b)))
-            .collect()
+            .filter_map(|t| {
+                let start = Instant::now();
+                let (t, p, d) = new_driver::<D>(t)?;
+                Some((t, Box::new(d), PacemakerHandle::spawn(p), start))
+            })
+            .collect();
+        let discover_deadline = *discover_deadline.get_or_insert_with(|| {
+            let t = Instant::now() + D::discover_timeout();
+            deadline.map_or(t, |deadline| t.min(deadline))
+        });
+        // 已经到期时，设备产生第一个事件后立即结束验证
+        let validate_deadline = (Instant::now() + validate_timeout).min(discover_deadline);
+        let need = len - drivers.len();
+        drivers.extend(probe(
+            wave,
+            need,
+            min_valid,
+            validate_deadline,
+            &validate,
+            cancel,
+        ));
+        dedupe_opened(&mut drivers);
+        if (!once || deadline.is_some()) && Instant::now() >= discover_deadline {
+            break;
+        }
+    }
+    debug!(opened = drivers.len(), elapsed = ?start.elapsed(), "probing end");
+    // 已经取消，关闭所有验证过的设备
+    if cancel.is_stopped() {
+        for (t, mut d, p, _) in drivers {
+            p.stop();
+            d.shutdown();
+            D::rejected(&t, RejectReason::Cancelled);
+        }
+        return Vec::new();
     }
+    truncate_opened(drivers, len)
+        .into_iter()
+        .map(|(t, d, p, latency)| {
+            debug!(?latency, "device accepted");
+            D::accepted(&t, latency);
+            (t, d, p)
+        })
+        .collect()
+}
+
+/// 打开 `t` 对应的驱动，超过 [`Driver::new_timeout`] 未完成时放弃
+#[cfg(feature = "std")]
+fn new_driver<D: Driver>(t: D::Key) -> Option<(D::Key, D::Pacemaker, D)>
+where
+    D::Key: Send + 'static,
+{
+    let timeout = D::new_timeout();
+    if timeout == Duration::MAX {
+        let (p, d) = D::new(&t)?;
+        return Some((t, p, d));
+    }
+    let (sender, receiver) = channel::bounded(1);
+    task::spawn_blocking(move || {
+        let opened = D::new(&t);
+        // 已经超时，关闭迟到的驱动
+        if let Err(e) = sender.try_send((t, opened)) {
+            if let (t, Some((_, mut d))) = e.into_inner() {
+                d.shutdown();
+                D::rejected(&t, RejectReason::OpenTimeout);
+            }
+        }
+    });
+    let (t, opened) = task::block_on(future::timeout(timeout, receiver.recv()))
+        .ok()?
+        .ok()?;
+    let (p, d) = opened?;
+    Some((t, p, d))
+}
+
+/// 打开临时的监控以筛除不产生正确输出的设备
+///
+/// 通过验证的设备多于 `need` 个时，它们将等到竞争者足够少或超时。
+#[cfg(feature = "std")]
+#[allow(clippy::type_complexity)]
+#[cfg_attr(not(feature = "tracing"), allow(clippy::unused_enumerate_index))]
+fn probe<D, V>(
+    drivers: Vec<(D::Key, Box<D>, PacemakerHandle<D::Pacemaker>, Instant)>,
+    need: usize,
+    min_valid: usize,
+    deadline: Instant,
+    validate: &V,
+    cancel: &Stop,
+) -> Vec<(
+    D::Key,
+    Box<D>,
+    PacemakerHandle<D::Pacemaker>,
+    Option<Duration>,
+)>
+where
+    D: Driver,
+    V: FnMut(&mut D, &D::Event) -> bool + Clone + Send + 'static,
+{
+    let counter = Arc::new(()); // ---------------------- // 用一个 Arc 来计数
+    #[allow(clippy::needless_collect)]
+    let drivers = drivers
+        .into_iter()
+        .enumerate()
+        .map(|(_candidate, (t, mut d, p, start))| {
+            let counter = counter.clone();
+            let mut validate = validate.clone();
+            let cancel = cancel.clone();
+            (
+                t,
+                p,
+                task::spawn_blocking(move || {
+                    let mut valid = 0;
+                    let mut latency = None;
+                    let ok = d.join(|d, e| {
+                        if let Some((_, e)) = &e {
+                            if valid < min_valid && validate(d, e) {
+                                valid += 1;
+                            }
+                            // 记录从打开到通过验证的耗时
+                            if latency.is_none() && valid >= min_valid {
+                                latency = Some(start.elapsed());
+                                trace!(
+                                    candidate = _candidate,
+                                    racing = Arc::strong_count(&counter),
+                                    need,
+                                    "candidate validated"
+                                );
+                            }
+                        }
+                        // 未通过验证的设备等到超时，通过验证的设备等到竞争者足够少
+                        let timeout = Instant::now() >= deadline || cancel.is_stopped();
+                        let racing = Arc::strong_count(&counter);
+                        let keep = !timeout && (valid < min_valid || racing > need);
+                        if !keep {
+                            debug!(
+                                candidate = _candidate,
+                                valid, racing, need, timeout, "candidate stopped probing"
+                            );
+                        }
+                        keep
+                    });
+                    let reason = if !ok {
+                        RejectReason::Exited
+                    } else if valid < min_valid && cancel.is_stopped() {
+                        RejectReason::Cancelled
+                    } else if valid < min_valid {
+                        RejectReason::Invalid { valid }
+                    } else {
+                        return Ok((d, latency));
+                    };
+                    d.shutdown();
+                    Err(reason)
+                }),
+            )
+        })
+        .collect::<Vec<_>>();
+    std::mem::drop(counter); // 丢弃外面的引用，此后引用计数 === 存活的驱动数
+                             // 收集正确打开的驱动
+    drivers
+        .into_iter()
+        .enumerate()
+        .filter_map(|(_candidate, (t, p, o))| match task::block_on(o) {
+            Ok((b, latency)) => Some((t, b, p, latency)),
+            Err(reason) => {
+                debug!(candidate = _candidate, ?reason, "candidate rejected");
+                p.stop();
+                D::rejected(&t, reason);
+                None
+            }
+        })
+        .collect()
+}
+
+/// 标识相同的驱动只保留优先级最高的一个，关闭其余驱动
+#[cfg(feature = "std")]
+#[allow(clippy::type_complexity)]
+fn dedupe_opened<D: Driver>(
+    drivers: &mut Vec<(
+        D::Key,
+        Box<D>,
+        PacemakerHandle<D::Pacemaker>,
+        Option<Duration>,
+    )>,
+) {
+    let mut identities = Vec::new();
+    let mut i = 0;
+    while i < drivers.len() {
+        match drivers[i].1.identity() {
+            Some(id) if identities.contains(&id) => {
+                let (t, mut d, p, _) = drivers.remove(i);
+                p.stop();
+                d.shutdown();
+                D::rejected(&t, RejectReason::Duplicate);
+            }
+            id => {
+                identities.extend(id);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// 只保留优先级最高的 `len` 个驱动，关闭其余驱动
+#[cfg(feature = "std")]
+#[allow(clippy::type_complexity)]
+fn truncate_opened<D: Driver>(
+    mut drivers: Vec<(
+        D::Key,
+        Box<D>,
+        PacemakerHandle<D::Pacemaker>,
+        Option<Duration>,
+    )>,
+    len: usize,
+) -> Vec<(
+    D::Key,
+    Box<D>,
+    PacemakerHandle<D::Pacemaker>,
+    Option<Duration>,
+)> {
+    for (t, mut d, p, _) in drivers.split_off(len.min(drivers.len())) {
+        p.stop();
+        d.shutdown();
+        D::rejected(&t, RejectReason::Surplus);
+    }
+    drivers
+}
+
+/// 打开的设备未被采用的原因
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RejectReason {
+    /// 验证期间驱动因错误退出
+    Exited,
+    /// 超时前通过检查的事件不足，`valid` 是通过检查的事件数
+    Invalid { valid: usize },
+    /// 通过验证的设备多于所需，优先级较低的设备被舍弃
+    Surplus,
+    /// 与优先级更高或已经在线的设备标识相同，见 [`Driver::identity`]
+    Duplicate,
+    /// 超过 [`Driver::new_timeout`] 才打开，此时已不再需要
+    OpenTimeout,
+    /// 打开驱动的过程被取消，见 [`Driver::open_some_cancellable`]
+    Cancelled,
 }
 
 /// 起搏器有一个静态不变的周期。
 ///
 /// 应该根据这个周期定时发送触发脉冲。
+#[cfg(feature = "std")]
 pub trait DriverPacemaker {
     /// 发送周期
     fn period() -> Duration;
 
     /// 发送一个触发脉冲，返回是否需要继续发送
-    fn send(&mut self) -> bool;
+    ///
+    /// 发送失败说明设备可能已经断开，监控器将视其驱动为断连并重新连接。
+    /// 需要经由驱动发送设备指令的保活见 [`MultipleDeviceDriver::keepalive`]，在此之后产生。
+    fn send(&mut self) -> io::Result<bool>;
+
+    /// 是否与周期相同的其他起搏器共用一个定时器
+    ///
+    /// 共用定时器的起搏器在同一时刻被依次触发，可以减少唤醒次数。
+    #[inline]
+    fn shared_timer() -> bool {
+        false
+    }
 }
 
 /// 空白起搏器，什么也不做，立即退出循环。
+#[cfg(feature = "std")]
 impl DriverPacemaker for () {
     #[inline]
     fn period() -> Duration {
@@ -117,7 +661,7 @@ impl DriverPacemaker for () {
     }
 
     #[inline]
-    fn send(&mut self) -> bool {
-        false
+    fn send(&mut self) -> io::Result<bool> {
+        Ok(false)
     }
 }
@@ -0,0 +1,121 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// 监控器运行时的统计量
+///
+/// 由监控器在内部更新，可以通过 `Arc` 共享到其他线程读取。
+#[derive(Default, Debug)]
+pub struct Metrics {
+    online: AtomicUsize,
+    connects: AtomicUsize,
+    connect_failures: AtomicUsize,
+    disconnects: AtomicUsize,
+    events: AtomicUsize,
+    missed_samples: AtomicUsize,
+    last_event: Mutex<Option<Instant>>,
+    next_try: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    /// 当前在线的设备数
+    #[inline]
+    pub fn online(&self) -> usize {
+        self.online.load(Relaxed)
+    }
+
+    /// 成功连接的总次数
+    #[inline]
+    pub fn connects(&self) -> usize {
+        self.connects.load(Relaxed)
+    }
+
+    /// 尝试连接但失败的总次数
+    #[inline]
+    pub fn connect_failures(&self) -> usize {
+        self.connect_failures.load(Relaxed)
+    }
+
+    /// 断开连接的总次数
+    #[inline]
+    pub fn disconnects(&self) -> usize {
+        self.disconnects.load(Relaxed)
+    }
+
+    /// 监听到的驱动事件总数
+    #[inline]
+    pub fn events(&self) -> usize {
+        self.events.load(Relaxed)
+    }
+
+    /// 按期望周期估计丢失的采样总数
+    #[inline]
+    pub fn missed_samples(&self) -> usize {
+        self.missed_samples.load(Relaxed)
+    }
+
+    /// 最后一次监听到驱动事件的时刻
+    #[inline]
+    pub fn last_event(&self) -> Option<Instant> {
+        *self.last_event.lock().unwrap()
+    }
+
+    /// 距最后一次监听到驱动事件经过的时间
+    #[inline]
+    pub fn since_last_event(&self) -> Option<Duration> {
+        self.last_event().map(|t| t.elapsed())
+    }
+
+    /// 下次尝试连接的时机，在线设备足够或不在监控时为 `None`
+    #[inline]
+    pub fn next_try(&self) -> Option<Instant> {
+        *self.next_try.lock().unwrap()
+    }
+
+    /// 距下次尝试连接的时间，已经到期时为 0
+    #[inline]
+    pub fn until_next_try(&self) -> Option<Duration> {
+        self.next_try()
+            .map(|t| t.saturating_duration_since(Instant::now()))
+    }
+
+    #[inline]
+    pub(crate) fn set_online(&self, n: usize) {
+        self.online.store(n, Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn set_next_try(&self, t: Option<Instant>) {
+        *self.next_try.lock().unwrap() = t;
+    }
+
+    #[inline]
+    pub(crate) fn connected(&self) {
+        self.connects.fetch_add(1, Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn connect_failed(&self) {
+        self.connect_failures.fetch_add(1, Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn disconnected(&self) {
+        self.disconnects.fetch_add(1, Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn missed(&self, n: usize) {
+        self.missed_samples.fetch_add(n, Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn event(&self) {
+        self.events.fetch_add(1, Relaxed);
+        *self.last_event.lock().unwrap() = Some(Instant::now());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{trace::debug, DriverPacemaker, Stop};
+use async_std::task;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+/// 在后台任务中运行的起搏器的句柄
+///
+/// 丢弃句柄不会停止起搏器。
+pub struct PacemakerHandle<P>(Arc<Shared<P>>);
+
+struct Shared<P> {
+    pacemaker: Mutex<P>,
+    paused: AtomicBool,
+    stopped: AtomicBool,
+    failed: Stop,
+    /// 每次成功发送触发脉冲后调用，返回 `false` 时移除
+    hook: Mutex<Option<Hook<P>>>,
+}
+
+type Hook<P> = Box<dyn FnMut(&mut P) -> bool + Send>;
+
+/// 共用定时器的起搏器组，按周期区分
+static GROUPS: Mutex<Vec<(Duration, Arc<Group>)>> = Mutex::new(Vec::new());
+
+type Group = Mutex<Vec<Arc<dyn Pulse>>>;
+
+/// 可以被定时器触发的起搏器
+trait Pulse: Send + Sync {
+    /// 发送一个触发脉冲，返回是否需要继续发送
+    fn pulse(&self) -> bool;
+}
+
+impl<P: DriverPacemaker + Send> Pulse for Shared<P> {
+    fn pulse(&self) -> bool {
+        if self.stopped.load(Ordering::Acquire) {
+            return false;
+        }
+        if self.paused.load(Ordering::Acquire) {
+            return true;
+        }
+        let mut pacemaker = self.pacemaker.lock().unwrap();
+        match pacemaker.send() {
+            Ok(true) => {
+                let mut hook = self.hook.lock().unwrap();
+                if hook.as_mut().is_some_and(|f| !f(&mut pacemaker)) {
+                    *hook = None;
+                }
+                return true;
+            }
+            Ok(false) => {}
+            Err(_e) => {
+                debug!(error = %_e, "pacemaker failed");
+                self.failed.stop();
+            }
+        }
+        self.stopped.store(true, Ordering::Release);
+        false
+    }
+}
+
+impl<P> Clone for PacemakerHandle<P> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<P: DriverPacemaker + Send + 'static> PacemakerHandle<P> {
+    /// 在后台任务中按周期发送触发脉冲
+    ///
+    /// 起搏器要求共用定时器时，加入同周期的起搏器组。
+    pub(crate) fn spawn(p: P) -> Self {
+        let shared = Arc::new(Shared {
+            pacemaker: Mutex::new(p),
+            paused: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            failed: Default::default(),
+            hook: Mutex::new(None),
+        });
+        if P::shared_timer() {
+            // 立即发送第一个脉冲，此后跟随组的定时器
+            if shared.pulse() {
+                join_group(P::period(), shared.clone());
+            }
+        } else {
+            let task_shared = shared.clone();
+            task::spawn(async move {
+                let period = P::period();
+                while task_shared.pulse() {
+                    task::sleep(period).await;
+                }
+            });
+        }
+        Self(shared)
+    }
+}
+
+/// 将起搏器加入周期为 `period` 的组，没有这样的组则创建一个并启动其定时器
+fn join_group(period: Duration, pulse: Arc<dyn Pulse>) {
+    let mut groups = GROUPS.lock().unwrap();
+    if let Some((_, group)) = groups.iter().find(|(p, _)| *p == period) {
+        group.lock().unwrap().push(pulse);
+        return;
+    }
+    let group = Arc::new(Mutex::new(vec![pulse]));
+    groups.push((period, group.clone()));
+    task::spawn(async move {
+        loop {
+            task::sleep(period).await;
+            let mut members = group.lock().unwrap();
+            members.retain(|p| p.pulse());
+            if !members.is_empty() {
+                continue;
+            }
+            drop(members);
+            // 组已空，解散前再次检查，先锁定组表再锁定组，与加入时的顺序一致
+            let mut groups = GROUPS.lock().unwrap();
+            if group.lock().unwrap().is_empty() {
+                groups.retain(|(_, g)| !Arc::ptr_eq(g, &group));
+                break;
+            }
+        }
+    });
+}
+
+impl<P> PacemakerHandle<P> {
+    /// 暂停发送触发脉冲
+    #[inline]
+    pub fn pause(&self) {
+        self.0.paused.store(true, Ordering::Release);
+    }
+
+    /// 恢复发送触发脉冲
+    #[inline]
+    pub fn resume(&self) {
+        self.0.paused.store(false, Ordering::Release);
+    }
+
+    /// 停止起搏器，停止后不能恢复
+    #[inline]
+    pub fn stop(&self) {
+        self.0.stopped.store(true, Ordering::Release);
+    }
+
+    /// 判断起搏器是否已停止，包括起搏器自己要求停止的情况
+    #[inline]
+    pub fn is_stopped(&self) -> bool {
+        self.0.stopped.load(Ordering::Acquire)
+    }
+
+    /// 判断起搏器是否因发送失败而停止
+    #[inline]
+    pub fn is_failed(&self) -> bool {
+        self.0.failed.is_stopped()
+    }
+
+    /// 起搏器发送失败的标记，供监控器检查
+    #[inline]
+    pub(crate) fn failure(&self) -> Stop {
+        self.0.failed.clone()
+    }
+
+    /// 此后每次成功发送触发脉冲，以起搏器调用 `f`，`f` 返回 `false` 时不再调用
+    ///
+    /// 替换之前设置的回调。
+    pub(crate) fn set_hook<F>(&self, f: F)
+    where
+        F: FnMut(&mut P) -> bool + Send + 'static,
+    {
+        *self.0.hook.lock().unwrap() = Some(Box::new(f));
+    }
+
+    /// 锁定起搏器以修改其配置
+    ///
+    /// 持有锁期间后台任务无法发送脉冲，应尽快释放。
+    #[inline]
+    pub fn lock(&self) -> MutexGuard<'_, P> {
+        self.0.pacemaker.lock().unwrap()
+    }
+}
@@ -0,0 +1,55 @@
+use async_std::channel::{self, Receiver, Sender};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// 暂停监控器的句柄
+///
+/// 可以克隆到其他线程。暂停期间设备保持打开，监控器不调用回调，恢复后继续。
+#[derive(Clone, Debug)]
+pub struct Pause(Arc<Shared>);
+
+#[derive(Debug)]
+struct Shared {
+    paused: AtomicBool,
+    wake: (Sender<()>, Receiver<()>),
+}
+
+impl Default for Pause {
+    #[inline]
+    fn default() -> Self {
+        Self(Arc::new(Shared {
+            paused: AtomicBool::new(false),
+            wake: channel::bounded(1),
+        }))
+    }
+}
+
+impl Pause {
+    /// 请求暂停
+    #[inline]
+    pub fn pause(&self) {
+        self.0.paused.store(true, Ordering::Release);
+    }
+
+    /// 恢复监控
+    #[inline]
+    pub fn resume(&self) {
+        self.0.paused.store(false, Ordering::Release);
+        let _ = self.0.wake.0.try_send(());
+    }
+
+    /// 判断是否已暂停
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::Acquire)
+    }
+
+    /// 等待恢复
+    pub(crate) async fn resumed(&self) {
+        while self.is_paused() {
+            let _ = self.0.wake.1.recv().await;
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+/// 按事件的时间戳检查固定频率设备的采样间隔
+///
+/// 间隔超过周期的 1.5 倍视作丢失了采样，丢失数按间隔相当的周期数估计。
+#[derive(Clone, Debug)]
+pub struct RateMonitor {
+    period: Duration,
+    last: Option<Instant>,
+    events: usize,
+    missed: usize,
+    jitter: Duration,
+    max_jitter: Duration,
+}
+
+impl RateMonitor {
+    /// 创建期望周期为 `period` 的监视器
+    #[inline]
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            last: None,
+            events: 0,
+            missed: 0,
+            jitter: Duration::ZERO,
+            max_jitter: Duration::ZERO,
+        }
+    }
+
+    /// 期望的周期
+    #[inline]
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// 记录一个事件的时间戳，发现间隔中丢失了采样时返回丢失数
+    ///
+    /// 只计算与上一个事件的间隔，时间戳早于上一个事件的事件被忽略。
+    pub fn record(&mut self, t: Instant) -> Option<usize> {
+        if self.last.is_some_and(|last| t < last) {
+            return None;
+        }
+        let last = self.last.replace(t);
+        self.events += 1;
+        let elapsed = t.duration_since(last?);
+        if self.period.is_zero() {
+            return None;
+        }
+        if elapsed * 2 > self.period * 3 {
+            // 按间隔中的周期数估计丢失的采样
+            let periods = (elapsed.as_secs_f64() / self.period.as_secs_f64()).round() as usize;
+            let missed = periods.max(2) - 1;
+            self.missed += missed;
+            Some(missed)
+        } else {
+            self.jitter = elapsed.abs_diff(self.period);
+            self.max_jitter = self.max_jitter.max(self.jitter);
+            None
+        }
+    }
+
+    /// 记录的事件总数
+    #[inline]
+    pub fn events(&self) -> usize {
+        self.events
+    }
+
+    /// 估计丢失的采样总数
+    #[inline]
+    pub fn missed(&self) -> usize {
+        self.missed
+    }
+
+    /// 最近一个未丢失采样的间隔与周期之差
+    #[inline]
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+
+    /// 未丢失采样的间隔与周期之差的最大值
+    #[inline]
+    pub fn max_jitter(&self) -> Duration {
+        self.max_jitter
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let ms = Duration::from_millis;
+        let start = Instant::now();
+        let mut monitor = RateMonitor::new(ms(10));
+        assert_eq!(monitor.record(start), None);
+        assert_eq!(monitor.record(start + ms(12)), None);
+        assert_eq!(monitor.jitter(), ms(2));
+        // 间隔相当于 5 个周期，丢失 4 个采样
+        assert_eq!(monitor.record(start + ms(62)), Some(4));
+        assert_eq!(monitor.record(start + ms(70)), None);
+        assert_eq!(monitor.events(), 4);
+        assert_eq!(monitor.missed(), 4);
+        assert_eq!(monitor.max_jitter(), ms(2));
+        // 乱序的事件不计数，也不影响下一个间隔
+        assert_eq!(monitor.record(start + ms(65)), None);
+        assert_eq!(monitor.events(), 4);
+        assert_eq!(monitor.record(start + ms(81)), None);
+        assert_eq!(monitor.jitter(), ms(1));
+    }
+}
@@ -0,0 +1,30 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// 从外部停止监控器的句柄
+///
+/// 可以克隆到其他线程，调用 [`Stop::stop`] 后监控器将在下一次检查时退出。
+#[derive(Clone, Default, Debug)]
+pub struct Stop(Arc<AtomicBool>);
+
+impl Stop {
+    /// 请求停止
+    #[inline]
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// 判断是否已请求停止
+    #[inline]
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// 撤销停止请求，之后监控器可以再次运行
+    #[inline]
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{Driver, MultipleDeviceDriver};
+use async_std::{
+    future,
+    stream::{Stream, StreamExt},
+    task,
+};
+use std::time::{Duration, Instant};
+
+/// 以异步事件流产生事件的设备，由 [`StreamDriver`] 适配为驱动
+pub trait StreamDevice: 'static + Send + Sized {
+    type Key: Send + 'static;
+    type Event;
+    type Command;
+    type Stream: Stream<Item = (Instant, Self::Event)> + Unpin + Send;
+
+    /// 所有可能的键，见 [`Driver::keys`]
+    fn keys() -> Vec<Self::Key>;
+    fn open_timeout() -> Duration;
+
+    /// 打开设备，返回设备和它的事件流
+    fn open(t: &Self::Key) -> Option<(Self, Self::Stream)>;
+
+    /// 向设备发送指令
+    fn send(&mut self, command: Self::Command);
+
+    /// 事件流没有事件时以 `None` 调用回调的间隔，回调借此处理指令和检查超时
+    #[inline]
+    fn idle_interval() -> Duration {
+        Duration::from_millis(100)
+    }
+}
+
+/// 将事件流设备适配为驱动，事件流结束视作断连
+///
+/// 监听期间阻塞等待事件流，长时间没有事件时每隔 [`StreamDevice::idle_interval`] 以 `None` 调用一次回调。
+pub struct StreamDriver<S: StreamDevice> {
+    device: S,
+    stream: S::Stream,
+}
+
+impl<S: StreamDevice> StreamDriver<S> {
+    /// 取得设备
+    #[inline]
+    pub fn device(&self) -> &S {
+        &self.device
+    }
+
+    /// 取得设备的可变引用
+    #[inline]
+    pub fn device_mut(&mut self) -> &mut S {
+        &mut self.device
+    }
+}
+
+impl<S: StreamDevice> Driver for StreamDriver<S> {
+    type Pacemaker = ();
+    type Key = S::Key;
+    type Event = S::Event;
+
+    #[inline]
+    fn keys() -> Vec<Self::Key> {
+        S::keys()
+    }
+
+    #[inline]
+    fn open_timeout() -> Duration {
+        S::open_timeout()
+    }
+
+    fn new(t: &Self::Key) -> Option<(Self::Pacemaker, Self)> {
+        let (device, stream) = S::open(t)?;
+        Some(((), Self { device, stream }))
+    }
+
+    fn join<F>(&mut self, mut f: F) -> bool
+    where
+        F: FnMut(&mut Self, Option<(Instant, Self::Event)>) -> bool,
+    {
+        task::block_on(async {
+            loop {
+                let e = match future::timeout(S::idle_interval(), self.stream.next()).await {
+                    Ok(Some(e)) => Some(e),
+                    Ok(None) => return false,
+                    // 没有事件，由回调处理指令并检查超时
+                    Err(_) => None,
+                };
+                if !f(self, e) {
+                    return true;
+                }
+            }
+        })
+    }
+}
+
+impl<S: StreamDevice> MultipleDeviceDriver for StreamDriver<S> {
+    type Command = S::Command;
+
+    #[inline]
+    fn send(&mut self, command: Self::Command) {
+        self.device.send(command);
+    }
+}
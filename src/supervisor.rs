@@ -0,0 +1,28 @@
+use crate::{Metrics, Stop};
+use std::sync::Arc;
+
+/// 监控器的共同接口，用于编写不区分设备数量的通用代码
+///
+/// 需要调整目标设备数等专有功能时，直接使用具体监控器的 `join`。
+pub trait Supervisor {
+    /// 监控期间产生的事件
+    type Event<'a>
+    where
+        Self: 'a;
+
+    /// 一次监控结束后的总结
+    type Report;
+
+    /// 监控至多 `len` 个设备，回调返回 `false` 时结束监控
+    ///
+    /// 单设备的监控器只区分 `len` 是否为 0，为 0 时立即返回。
+    fn supervise<F>(&mut self, len: usize, f: F) -> Self::Report
+    where
+        F: for<'a> FnMut(Self::Event<'a>) -> bool;
+
+    /// 获取监控器的统计量，首次调用时启用统计
+    fn metrics(&mut self) -> Arc<Metrics>;
+
+    /// 获取停止句柄，用于从其他线程结束监控
+    fn stop_handle(&self) -> Stop;
+}
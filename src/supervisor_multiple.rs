@@ -1,18 +1,99 @@
-﻿use super::Driver;
-use std::{hash::Hash, sync::mpsc, time::Instant};
+﻿use super::{Driver, Metrics, PacemakerHandle, Pause, RateMonitor, Stop, Supervisor};
+use async_std::task;
+use std::{
+    hash::Hash,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
+mod command;
 mod context;
 
+pub use command::{CommandNotify, CommandRouter, CommandSender, NotConnected, Replier, Reply};
+
+/// 可以接收指令的驱动
+///
+/// 需要回复的指令可以携带 [`Replier`]，由驱动在设备响应后回复，见 [`CommandSender::request`]。
 pub trait MultipleDeviceDriver: Driver {
     type Command;
 
     fn send(&mut self, command: Self::Command);
+
+    /// 监控器送出每条指令的时限，见 [`send_timeout`](Self::send_timeout)
+    #[inline]
+    fn command_timeout() -> Duration {
+        Duration::MAX
+    }
+
+    /// 在 `timeout` 内送出指令，未能送达时交还指令
+    ///
+    /// 监控器在设备线程中调用此方法，未送达的指令产生 [`SupervisorEventForMultiple::CommandDropped`]。
+    /// 默认调用 [`send`](Self::send) 并总是成功，写入可能阻塞的驱动应该实现此方法，以免阻塞设备事件。
+    #[inline]
+    fn send_timeout(
+        &mut self,
+        command: Self::Command,
+        _timeout: Duration,
+    ) -> Result<(), Self::Command> {
+        self.send(command);
+        Ok(())
+    }
+
+    /// 起搏器每次成功发送触发脉冲后，由起搏器产生一条保活指令，返回 `None` 时本周期不发送
+    ///
+    /// 保活指令与其他指令经过同样的通道，由驱动的 [`send`](Self::send) 或 [`send_timeout`](Self::send_timeout) 送出，
+    /// 因此只在监控期间发送，起搏器暂停或停止时随之停止。之前的指令尚未送出时不产生新的保活指令。
+    /// [`SupervisorForSingle`](crate::SupervisorForSingle) 需要通过其 `join_with_keepalive` 启用。
+    #[inline]
+    fn keepalive(_pacemaker: &mut Self::Pacemaker) -> Option<Self::Command> {
+        None
+    }
+
+    /// 开始监控前，监控器通过此方法交给驱动一个指令通知
+    ///
+    /// 长时间不产生事件的驱动应该在等待时检查通知，以免指令迟迟无法送达。
+    #[inline]
+    fn set_command_notify(&mut self, _notify: CommandNotify) {}
 }
 
-pub struct SupervisorForMultiple<D: Driver>(Vec<(D::Key, Box<D>)>);
+pub struct SupervisorForMultiple<D: MultipleDeviceDriver> {
+    /// 保存的驱动对象及其起搏器，从外部传入的驱动对象没有起搏器
+    #[allow(clippy::type_complexity)]
+    drivers: Vec<(D::Key, Box<D>, Option<PacemakerHandle<D::Pacemaker>>)>,
+    /// 上次监控结束时的下次尝试连接的时机
+    next_try: Option<Instant>,
+    metrics: Option<Arc<Metrics>>,
+    router: Option<CommandRouter<D::Key, D::Command>>,
+    liveness_timeout: Option<Duration>,
+    max_connect_attempts: Option<usize>,
+    max_devices: Option<usize>,
+    /// 每个时段内最多打开的设备数
+    spawn_rate: Option<(usize, Duration)>,
+    /// 隔离频繁断连的设备：时段内的断连次数、时段长度和隔离时长
+    quarantine: Option<(usize, Duration, Duration)>,
+    pause: Pause,
+    stop: Stop,
+    buffer_while_paused: bool,
+    expected_period: Option<Duration>,
+    drain_on_exit: bool,
+    flush_after_callback: bool,
+    batch_events: bool,
+    command_capacity: usize,
+    #[allow(clippy::type_complexity)]
+    thread_builder: Option<Box<dyn Fn(&D::Key) -> thread::Builder + Send + Sync>>,
+    #[allow(clippy::type_complexity)]
+    thread_init: Option<Arc<dyn Fn(&D::Key) + Send + Sync>>,
+    #[allow(clippy::type_complexity)]
+    command_middleware:
+        Option<Arc<dyn Fn(&D::Key, D::Command) -> Option<D::Command> + Send + Sync>>,
+}
 
 pub enum SupervisorEventForMultiple<'a, D: MultipleDeviceDriver> {
+    /// 设备在本次监控中首次连接
     Connected(&'a D::Key, &'a mut D),
+    /// 本次监控中连接过的设备断连后重新连接，监控开始时保存在监控器中的设备也视作连接过
+    Reconnected(&'a D::Key, &'a mut D),
     ConnectFailed {
         current: usize,
         target: usize,
@@ -21,15 +102,139 @@ pub enum SupervisorEventForMultiple<'a, D: MultipleDeviceDriver> {
     Event(
         D::Key,
         Option<(Instant, D::Event)>,
-        &'a mpsc::Sender<D::Command>,
+        &'a CommandSender<D::Command>,
     ),
-    Disconnected(D::Key),
+    /// 同一设备已经到达的多个事件，按产生顺序合并交给回调
+    ///
+    /// 只在启用合并时产生，见 [`SupervisorForMultiple::with_batch_events`]。
+    EventBatch(
+        D::Key,
+        Vec<(Instant, D::Event)>,
+        &'a CommandSender<D::Command>,
+    ),
+    /// 一轮连接打开了至少一个设备，在本轮的 [`Connected`](Self::Connected) 之后产生
+    ///
+    /// `requested` 是本轮需要的设备数，`opened` 是实际打开的设备数。一个设备都没打开时产生的是 [`ConnectFailed`](Self::ConnectFailed)。
+    ProbeRound { requested: usize, opened: usize },
+    /// 设备事件的间隔中丢失了采样，在这个事件的 [`Event`](Self::Event) 之前产生
+    ///
+    /// 只在设置了期望周期时检查，见 [`SupervisorForMultiple::with_expected_period`]。
+    Gap {
+        key: D::Key,
+        missed: usize,
+        monitor: &'a RateMonitor,
+    },
+    /// 设备断连，设备线程因错误退出时交还驱动对象，可以从中读取诊断信息
+    ///
+    /// 设备线程发生恐慌时不交还驱动对象，同时记入 [`JoinReport::panicked`]。
+    /// 无法创建设备线程时也不交还驱动对象，它已回收到监控器中，见 [`DisconnectReason::SpawnFailed`]。
+    Disconnected(D::Key, Option<Box<D>>, DisconnectReason),
+    /// 设备频繁断连，直到 `until` 前不再打开，在这次断连的 [`Disconnected`](Self::Disconnected) 之前产生
+    ///
+    /// 只在启用隔离时产生，见 [`SupervisorForMultiple::with_quarantine`]。
+    Quarantined { key: D::Key, until: Instant },
+    /// 设备的隔离期满，之后可以再次打开
+    QuarantineEnded(D::Key),
+    /// 本次监控中在线设备数首次达到目标设备数，目标设备数超过设备数上限时以上限为准
+    TargetReached { count: usize },
+    /// 设备的驱动对象回收到监控器中，与 [`JoinReport::reclaimed`] 一一对应，回调的返回值被忽略
+    Reclaimed(D::Key),
+    /// 监控结束时未能回收设备，其线程因错误退出或发生恐慌，回调的返回值被忽略
+    ReclaimFailed(D::Key),
+    /// 连续失败次数达到上限，不再打开新的设备
+    ///
+    /// 已在线的设备继续监控，全部断连后监控结束。
+    ConnectAbandoned,
+    /// 指令未能在 [`MultipleDeviceDriver::command_timeout`] 内送达设备，交还指令
+    CommandDropped(D::Key, D::Command),
+}
+
+/// 设备断连的原因，见 [`SupervisorEventForMultiple::Disconnected`]
+///
+/// 驱动自身的错误原因需要从交还的驱动对象中读取。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DisconnectReason {
+    /// 驱动的 [`join`](Driver::join) 因错误退出
+    Exited,
+    /// 起搏器发送失败
+    PacemakerFailed,
+    /// 设备线程发生恐慌
+    Panicked,
+    /// 超过存活时限没有产生事件，见 [`SupervisorForMultiple::with_liveness_timeout`]
+    Silent,
+    /// 系统无法为设备创建线程，驱动对象回收到监控器中，下次监控时重试
+    SpawnFailed,
+}
+
+/// 一次监控结束后的总结
+#[derive(Clone, Debug)]
+pub struct JoinReport<K> {
+    /// 回收到监控器中的设备
+    pub reclaimed: Vec<K>,
+    /// 结束时未能回收的设备，它们的线程因错误退出
+    pub lost: Vec<K>,
+    /// 线程发生恐慌的设备，包括监控期间和结束时发现的
+    pub panicked: Vec<K>,
+    /// 成功连接的次数
+    pub connects: usize,
+    /// 尝试连接但失败的次数
+    pub connect_failures: usize,
+    /// 断开连接的次数
+    pub disconnects: usize,
+}
+
+impl<K> Default for JoinReport<K> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            reclaimed: Vec::new(),
+            lost: Vec::new(),
+            panicked: Vec::new(),
+            connects: 0,
+            connect_failures: 0,
+            disconnects: 0,
+        }
+    }
 }
 
 impl<D: MultipleDeviceDriver> Default for SupervisorForMultiple<D> {
     #[inline]
     fn default() -> Self {
-        Self(Vec::new())
+        Self {
+            drivers: Vec::new(),
+            next_try: None,
+            metrics: None,
+            router: None,
+            liveness_timeout: None,
+            max_connect_attempts: None,
+            max_devices: None,
+            spawn_rate: None,
+            quarantine: None,
+            pause: Default::default(),
+            stop: Default::default(),
+            buffer_while_paused: false,
+            expected_period: None,
+            drain_on_exit: false,
+            flush_after_callback: false,
+            batch_events: false,
+            command_capacity: usize::MAX,
+            thread_builder: None,
+            thread_init: None,
+            command_middleware: None,
+        }
+    }
+}
+
+impl<D: MultipleDeviceDriver> From<Vec<(D::Key, Box<D>)>> for SupervisorForMultiple<D> {
+    /// 监控传入的驱动程序，下次监控时直接使用而不重新打开
+    ///
+    /// 监控器不管理这些驱动的起搏器。
+    #[inline]
+    fn from(drivers: Vec<(D::Key, Box<D>)>) -> Self {
+        Self {
+            drivers: drivers.into_iter().map(|(k, d)| (k, d, None)).collect(),
+            ..Default::default()
+        }
     }
 }
 
@@ -44,11 +249,603 @@ where
         Default::default()
     }
 
+    /// 设置设备的存活超时
+    ///
+    /// 超过 `timeout` 未产生事件的设备将被视作断连。
+    #[inline]
+    pub fn with_liveness_timeout(mut self, timeout: Duration) -> Self {
+        self.liveness_timeout = Some(timeout);
+        self
+    }
+
+    /// 设置连续尝试连接的次数上限，任何设备成功连接后重新计数
+    ///
+    /// 达到上限后不再打开新的设备，见 [`SupervisorEventForMultiple::ConnectAbandoned`]。
+    #[inline]
+    pub fn with_max_connect_attempts(mut self, n: usize) -> Self {
+        self.max_connect_attempts = Some(n);
+        self
+    }
+
+    /// 设置同时在线的设备数上限，目标设备数超过上限时按上限连接
+    ///
+    /// 上限在保存每个设备时检查，超出上限的设备即使已经打开也将被关闭。
+    #[inline]
+    pub fn with_max_devices(mut self, n: usize) -> Self {
+        self.max_devices = Some(n);
+        self
+    }
+
+    /// 限制打开设备的速率，任意 `interval` 时段内最多打开 `n` 个设备
+    ///
+    /// 大量设备同时断连时逐步重新连接，额度用完时等到额度恢复再尝试，不视作连接失败。
+    /// 每轮连接中同时尝试打开的端口数由 [`Driver::max_probes`] 限制。
+    #[inline]
+    pub fn with_spawn_rate(mut self, n: usize, interval: Duration) -> Self {
+        self.spawn_rate = Some((n.max(1), interval));
+        self
+    }
+
+    /// 隔离频繁断连的设备，`window` 时段内断连 `n` 次的键在 `cooldown` 内不再打开
+    ///
+    /// 隔离开始时产生 [`Quarantined`](SupervisorEventForMultiple::Quarantined)，
+    /// 结束时产生 [`QuarantineEnded`](SupervisorEventForMultiple::QuarantineEnded)。
+    /// 隔离期满后在下一轮连接前解除隔离。
+    #[inline]
+    pub fn with_quarantine(mut self, n: usize, window: Duration, cooldown: Duration) -> Self {
+        self.quarantine = Some((n.max(1), window, cooldown));
+        self
+    }
+
+    /// 设置设备产生事件的期望周期，按事件的时间戳检查采样间隔
+    ///
+    /// 发现丢失采样时产生 [`Gap`](SupervisorEventForMultiple::Gap) 事件。
+    #[inline]
+    pub fn with_expected_period(mut self, period: Duration) -> Self {
+        self.expected_period = Some(period);
+        self
+    }
+
+    /// 设备线程等待回调处理完每个事件，回调中发送的指令在驱动再次阻塞前送达
+    ///
+    /// 可以缩短对事件作出反应的延迟，但设备线程产生事件的速度受限于回调。
+    #[inline]
+    pub fn with_flush_after_callback(mut self) -> Self {
+        self.flush_after_callback = true;
+        self
+    }
+
+    /// 将同一设备已经到达的事件合并为 [`EventBatch`](SupervisorEventForMultiple::EventBatch) 交给回调
+    ///
+    /// 适用于产生事件很快而回调每次调用的开销较大的情况。不带事件的回调仍然产生 [`Event`](SupervisorEventForMultiple::Event)。
+    #[inline]
+    pub fn with_batch_events(mut self) -> Self {
+        self.batch_events = true;
+        self
+    }
+
+    /// 设置每个设备排队的指令数上限，异步发送的指令达到上限时等待设备取走指令
+    ///
+    /// 见 [`CommandSender::send_async`]。同步发送不受限制。
+    #[inline]
+    pub fn with_command_capacity(mut self, n: usize) -> Self {
+        self.command_capacity = n.max(1);
+        self
+    }
+
+    /// 为每个设备创建独立的线程，`f` 根据设备的键设置线程的名字和栈大小
+    ///
+    /// 默认在阻塞任务的线程池中监听设备，线程没有可以区分设备的名字。
+    #[inline]
+    pub fn with_thread_builder<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&D::Key) -> thread::Builder + Send + Sync + 'static,
+    {
+        self.thread_builder = Some(Box::new(f));
+        self
+    }
+
+    /// 设备线程开始监听设备前调用 `f`，可以在此设置线程的调度优先级
+    #[inline]
+    pub fn with_thread_init<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&D::Key) + Send + Sync + 'static,
+    {
+        self.thread_init = Some(Arc::new(f));
+        self
+    }
+
+    /// 指令送达设备前统一经过 `f`，可以在此检查、限制或修改指令，返回 `None` 的指令被丢弃
+    ///
+    /// `f` 在各个设备线程中调用。
+    #[inline]
+    pub fn with_command_middleware<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&D::Key, D::Command) -> Option<D::Command> + Send + Sync + 'static,
+    {
+        self.command_middleware = Some(Arc::new(f));
+        self
+    }
+
+    /// 监控结束前处理完通道中剩余的消息
+    ///
+    /// 目标设备数变为 0 后，等待仍在监控的设备线程退出，期间收到的事件和断连仍交给回调，回调的返回值被忽略。
+    /// 因存活超时而放弃的设备线程不再等待。
+    #[inline]
+    pub fn with_drain_on_exit(mut self) -> Self {
+        self.drain_on_exit = true;
+        self
+    }
+
+    /// 暂停期间缓存设备事件，恢复后依次交给回调
+    ///
+    /// 默认丢弃暂停期间的设备事件，但断连仍会在恢复后报告。
+    #[inline]
+    pub fn with_buffer_while_paused(mut self) -> Self {
+        self.buffer_while_paused = true;
+        self
+    }
+
+    /// 查看回收到监控器中的驱动对象
+    ///
+    /// 监控结束后，仍然在线的设备回收到监控器中，下次监控时直接使用。
+    #[inline]
+    pub fn drivers(&self) -> impl Iterator<Item = (&D::Key, &D)> {
+        self.drivers.iter().map(|(k, d, _)| (k, d.as_ref()))
+    }
+
+    /// 取出回收到监控器中的驱动对象，取出后下次监控需要重新打开设备
+    #[inline]
+    pub fn take_drivers(&mut self) -> Vec<(D::Key, Box<D>)> {
+        self.drivers.drain(..).map(|(k, d, _)| (k, d)).collect()
+    }
+
+    /// 下次尝试连接的时机，跨越多次监控保留
+    ///
+    /// 尚未监控过时返回 `None`，此时监控开始即尝试连接。
+    /// 监控期间需要读取时，使用 [`metrics`](Self::metrics) 得到的共享统计量。
+    #[inline]
+    pub fn next_try(&self) -> Option<Instant> {
+        self.next_try
+    }
+
+    /// 设置下次监控时尝试连接的时机，`None` 表示立即尝试
+    #[inline]
+    pub fn set_next_try(&mut self, t: Option<Instant>) {
+        self.next_try = t;
+    }
+
+    /// 获取暂停句柄，用于从其他线程暂停和恢复 [`join`](Self::join)
+    ///
+    /// 暂停期间不检查设备存活，也不尝试连接新的设备。
+    #[inline]
+    pub fn pause_handle(&self) -> Pause {
+        self.pause.clone()
+    }
+
+    /// 获取停止句柄，用于从其他线程结束 [`join`](Self::join)
+    ///
+    /// 停止后正在进行的连接立即取消，监控器在下一次检查时回收所有设备并结束监控。
+    /// 监控器在每轮连接和每条设备消息到来时检查句柄。
+    #[inline]
+    pub fn stop_handle(&self) -> Stop {
+        self.stop.clone()
+    }
+
+    /// 获取监控器的统计量，首次调用时启用统计
+    pub fn metrics(&mut self) -> Arc<Metrics> {
+        self.metrics.get_or_insert_with(Default::default).clone()
+    }
+
+    /// 获取按键发送指令的路由，首次调用时启用路由
+    ///
+    /// 路由只在监控期间包含在线的设备。
+    pub fn command_router(&mut self) -> CommandRouter<D::Key, D::Command> {
+        self.router.get_or_insert_with(Default::default).clone()
+    }
+
+    /// 使用监控器监控驱动程序，结束后返回本次监控的总结
+    ///
+    /// 回调 `f` 的第二个参数是当前的目标设备数，返回值是新的目标设备数，目标设备数为 0 时监控结束。
+    /// `init_len` 为 0 时立即返回，监控器中保存的驱动对象原样保留并记入 [`JoinReport::reclaimed`]，
+    /// 回调只对其中每个设备收到 [`Reclaimed`](SupervisorEventForMultiple::Reclaimed)。
     #[inline]
-    pub fn join<F>(&mut self, init_len: usize, f: F)
+    pub fn join<F>(&mut self, init_len: usize, f: F) -> JoinReport<D::Key>
     where
-        F: FnMut(SupervisorEventForMultiple<D>) -> usize,
+        F: FnMut(SupervisorEventForMultiple<D>, usize) -> usize,
     {
-        context::JoinContextForMultiple::new(self, init_len, f).run();
+        task::block_on(self.join_async(init_len, f))
+    }
+
+    /// 异步地监控驱动程序，结束后返回本次监控的总结
+    ///
+    /// 在结束前丢弃返回的 `Future` 将取消监控，此时在线设备的驱动对象无法回收到监控器。
+    pub async fn join_async<F>(&mut self, init_len: usize, mut f: F) -> JoinReport<D::Key>
+    where
+        F: FnMut(SupervisorEventForMultiple<D>, usize) -> usize,
+    {
+        // 不需要任何设备，不启动设备线程
+        if init_len == 0 {
+            let reclaimed = self
+                .drivers
+                .iter()
+                .map(|(k, _, _)| k.clone())
+                .collect::<Vec<_>>();
+            for k in &reclaimed {
+                f(SupervisorEventForMultiple::Reclaimed(k.clone()), 0);
+            }
+            return JoinReport {
+                reclaimed,
+                ..Default::default()
+            };
+        }
+        let run = context::JoinContextForMultiple::new(self, init_len, f).run();
+        #[cfg(feature = "tracing")]
+        let run = tracing::Instrument::instrument(run, tracing::debug_span!("supervise", init_len));
+        run.await
+    }
+}
+
+impl<D: MultipleDeviceDriver> Supervisor for SupervisorForMultiple<D>
+where
+    D::Key: Send + Clone + Eq + Hash,
+    D::Event: Send,
+    D::Command: Send,
+{
+    type Event<'a> = SupervisorEventForMultiple<'a, D>;
+    type Report = JoinReport<D::Key>;
+
+    /// 目标设备数保持为 `len`，回调返回 `false` 时降为 0
+    #[inline]
+    fn supervise<F>(&mut self, len: usize, mut f: F) -> Self::Report
+    where
+        F: for<'a> FnMut(Self::Event<'a>) -> bool,
+    {
+        self.join(len, |e, len| if f(e) { len } else { 0 })
+    }
+
+    #[inline]
+    fn metrics(&mut self) -> Arc<Metrics> {
+        SupervisorForMultiple::metrics(self)
+    }
+
+    #[inline]
+    fn stop_handle(&self) -> Stop {
+        SupervisorForMultiple::stop_handle(self)
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use async_std::future;
+
+    /// 打开后永不产生事件也永不退出的设备
+    struct Silent;
+
+    impl Driver for Silent {
+        type Pacemaker = ();
+        type Key = u8;
+        type Event = ();
+
+        fn keys() -> Vec<Self::Key> {
+            vec![0]
+        }
+
+        fn open_timeout() -> Duration {
+            Duration::ZERO
+        }
+
+        fn new(_t: &Self::Key) -> Option<(Self::Pacemaker, Self)> {
+            Some(((), Self))
+        }
+
+        fn join<F>(&mut self, _f: F) -> bool
+        where
+            F: FnMut(&mut Self, Option<(Instant, Self::Event)>) -> bool,
+        {
+            loop {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    impl MultipleDeviceDriver for Silent {
+        type Command = ();
+
+        fn send(&mut self, _command: Self::Command) {}
+    }
+
+    /// 打开后只以 `None` 调用回调的设备
+    struct Idle;
+
+    impl Driver for Idle {
+        type Pacemaker = ();
+        type Key = u8;
+        type Event = ();
+
+        fn keys() -> Vec<Self::Key> {
+            vec![0]
+        }
+
+        fn open_timeout() -> Duration {
+            Duration::ZERO
+        }
+
+        fn new(_t: &Self::Key) -> Option<(Self::Pacemaker, Self)> {
+            Some(((), Self))
+        }
+
+        fn join<F>(&mut self, mut f: F) -> bool
+        where
+            F: FnMut(&mut Self, Option<(Instant, Self::Event)>) -> bool,
+        {
+            while f(self, None) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            true
+        }
+    }
+
+    impl MultipleDeviceDriver for Idle {
+        type Command = ();
+
+        fn send(&mut self, _command: Self::Command) {}
+    }
+
+    /// 只能打开一次的设备，打开后持续产生事件
+    struct OpenOnce;
+
+    static OPENED_ONCE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+    impl Driver for OpenOnce {
+        type Pacemaker = ();
+        type Key = u8;
+        type Event = ();
+
+        fn keys() -> Vec<Self::Key> {
+            vec![0]
+        }
+
+        fn open_timeout() -> Duration {
+            Duration::ZERO
+        }
+
+        fn new(_t: &Self::Key) -> Option<(Self::Pacemaker, Self)> {
+            (!OPENED_ONCE.swap(true, std::sync::atomic::Ordering::Relaxed)).then_some(((), Self))
+        }
+
+        fn join<F>(&mut self, mut f: F) -> bool
+        where
+            F: FnMut(&mut Self, Option<(Instant, Self::Event)>) -> bool,
+        {
+            while f(self, Some((Instant::now(), ()))) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            true
+        }
+    }
+
+    impl MultipleDeviceDriver for OpenOnce {
+        type Command = ();
+
+        fn send(&mut self, _command: Self::Command) {}
+    }
+
+    /// 累计发送的触发脉冲数
+    static PULSES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    /// 记录触发脉冲数的起搏器
+    struct Counting;
+
+    impl crate::DriverPacemaker for Counting {
+        fn period() -> Duration {
+            Duration::from_millis(5)
+        }
+
+        fn send(&mut self) -> std::io::Result<bool> {
+            PULSES.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(true)
+        }
+    }
+
+    /// 与 [`Silent`] 相同，但带有起搏器
+    struct Stuck;
+
+    impl Driver for Stuck {
+        type Pacemaker = Counting;
+        type Key = u8;
+        type Event = ();
+
+        fn keys() -> Vec<Self::Key> {
+            vec![0]
+        }
+
+        fn open_timeout() -> Duration {
+            Duration::ZERO
+        }
+
+        fn new(_t: &Self::Key) -> Option<(Self::Pacemaker, Self)> {
+            Some((Counting, Self))
+        }
+
+        fn join<F>(&mut self, _f: F) -> bool
+        where
+            F: FnMut(&mut Self, Option<(Instant, Self::Event)>) -> bool,
+        {
+            loop {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    impl MultipleDeviceDriver for Stuck {
+        type Command = ();
+
+        fn send(&mut self, _command: Self::Command) {}
+    }
+
+    /// 每个周期产生一条递增的保活指令的起搏器
+    struct Beat(usize);
+
+    impl crate::DriverPacemaker for Beat {
+        fn period() -> Duration {
+            Duration::from_millis(5)
+        }
+
+        fn send(&mut self) -> std::io::Result<bool> {
+            Ok(true)
+        }
+    }
+
+    /// 不断以 `None` 调用回调，记录收到的保活指令
+    struct Beating;
+
+    static BEATS: std::sync::Mutex<Vec<usize>> = std::sync::Mutex::new(Vec::new());
+
+    impl Driver for Beating {
+        type Pacemaker = Beat;
+        type Key = u8;
+        type Event = ();
+
+        fn keys() -> Vec<Self::Key> {
+            vec![0]
+        }
+
+        fn open_timeout() -> Duration {
+            Duration::ZERO
+        }
+
+        fn new(_t: &Self::Key) -> Option<(Self::Pacemaker, Self)> {
+            Some((Beat(0), Self))
+        }
+
+        fn join<F>(&mut self, mut f: F) -> bool
+        where
+            F: FnMut(&mut Self, Option<(Instant, Self::Event)>) -> bool,
+        {
+            while f(self, None) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            true
+        }
+    }
+
+    impl MultipleDeviceDriver for Beating {
+        type Command = usize;
+
+        fn send(&mut self, command: Self::Command) {
+            BEATS.lock().unwrap().push(command);
+        }
+
+        fn keepalive(pacemaker: &mut Self::Pacemaker) -> Option<Self::Command> {
+            pacemaker.0 += 1;
+            Some(pacemaker.0)
+        }
+    }
+
+    #[test]
+    fn keepalive() {
+        let mut supervisor = SupervisorForMultiple::<Beating>::new();
+        let join = supervisor.join_async(1, |_, len| {
+            if BEATS.lock().unwrap().len() >= 3 {
+                0
+            } else {
+                len
+            }
+        });
+        task::block_on(future::timeout(Duration::from_secs(5), join))
+            .expect("keepalive commands never reached the driver");
+        assert_eq!(BEATS.lock().unwrap()[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn idle_is_silent() {
+        let mut supervisor =
+            SupervisorForMultiple::<Idle>::new().with_liveness_timeout(Duration::from_millis(20));
+        let mut silent = false;
+        let join = supervisor.join_async(1, |e, len| match e {
+            SupervisorEventForMultiple::Disconnected(_, _, DisconnectReason::Silent) => {
+                silent = true;
+                0
+            }
+            _ => len,
+        });
+        task::block_on(future::timeout(Duration::from_secs(5), join))
+            .expect("a device ticking without events was never reported silent");
+        assert!(silent);
+    }
+
+    #[test]
+    fn abandoned_pacemaker() {
+        let mut supervisor =
+            SupervisorForMultiple::<Stuck>::new().with_liveness_timeout(Duration::from_millis(20));
+        let join = supervisor.join_async(1, |e, len| match e {
+            SupervisorEventForMultiple::Disconnected(..) => 0,
+            _ => len,
+        });
+        task::block_on(future::timeout(Duration::from_secs(5), join))
+            .expect("the stuck device was never reported silent");
+        // 设备线程仍未退出，起搏器随设备被放弃而停止
+        thread::sleep(Duration::from_millis(20));
+        let pulses = PULSES.load(std::sync::atomic::Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(PULSES.load(std::sync::atomic::Ordering::Relaxed), pulses);
+    }
+
+    #[test]
+    fn connect_abandoned() {
+        use SupervisorEventForMultiple::*;
+
+        let mut supervisor = SupervisorForMultiple::<OpenOnce>::new().with_max_connect_attempts(2);
+        let mut abandoned = false;
+        let mut events = 0;
+        let join = supervisor.join_async(2, |e, len| match e {
+            ConnectFailed { next_try, .. } => {
+                *next_try = Instant::now();
+                len
+            }
+            ConnectAbandoned => {
+                abandoned = true;
+                len
+            }
+            // 放弃连接后，在线的设备仍在监控中
+            Event(..) if abandoned => {
+                events += 1;
+                if events >= 3 {
+                    0
+                } else {
+                    len
+                }
+            }
+            _ => len,
+        });
+        let report = task::block_on(future::timeout(Duration::from_secs(5), join))
+            .expect("the online device stopped being supervised");
+        assert!(abandoned);
+        assert_eq!(events, 3);
+        assert_eq!(report.connects, 1);
+        assert_eq!(report.disconnects, 0);
+        assert_eq!(report.reclaimed, vec![0]);
+    }
+
+    #[test]
+    fn drain_abandoned() {
+        let mut supervisor = SupervisorForMultiple::<Silent>::new()
+            .with_liveness_timeout(Duration::from_millis(20))
+            .with_drain_on_exit();
+        let mut silent = false;
+        let join = supervisor.join_async(1, |e, len| match e {
+            SupervisorEventForMultiple::Disconnected(_, _, DisconnectReason::Silent) => {
+                silent = true;
+                0
+            }
+            _ => len,
+        });
+        let report = task::block_on(future::timeout(Duration::from_secs(5), join))
+            .expect("supervisor hung on an abandoned device thread");
+        assert!(silent);
+        assert_eq!(report.disconnects, 1);
+        assert!(report.reclaimed.is_empty());
     }
 }
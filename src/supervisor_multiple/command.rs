@@ -0,0 +1,291 @@
+use async_std::channel;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+};
+
+/// 向设备发送指令的通道
+///
+/// 发送指令的同时触发设备的 [`CommandNotify`]，使阻塞等待中的驱动尽快取走指令。
+pub struct CommandSender<C> {
+    sender: mpsc::Sender<C>,
+    notify: CommandNotify,
+    pending: Arc<AtomicUsize>,
+    capacity: usize,
+    /// 指令被取走时发出信号，唤醒等待空位的异步发送
+    room: (channel::Sender<()>, channel::Receiver<()>),
+}
+
+/// 设备线程取出指令的一端，丢弃时唤醒所有等待空位的异步发送
+pub(super) struct CommandReceiver<C> {
+    receiver: mpsc::Receiver<C>,
+    pending: Arc<AtomicUsize>,
+    room: channel::Sender<()>,
+}
+
+/// 设备有待处理指令的通知
+///
+/// 驱动在 [`join`](crate::Driver::join) 中阻塞等待时应检查通知，
+/// 通知触发后应尽快调用回调（可以不带事件），监控器将在回调中把指令交给驱动。
+#[derive(Clone, Default, Debug)]
+pub struct CommandNotify(Arc<AtomicBool>);
+
+/// 按键向在线设备发送指令的路由，随设备连接和断连同步更新
+///
+/// 从监控器获取，可以克隆到其他线程，在监控回调之外向指定设备发送指令。
+pub struct CommandRouter<K, C>(Arc<Mutex<HashMap<K, CommandSender<C>>>>);
+
+/// 设备不在线，交还指令
+#[derive(Debug)]
+pub struct NotConnected<C>(pub C);
+
+/// 驱动回复请求的一端，随指令交给驱动
+///
+/// 驱动收到设备对请求的响应后调用 [`reply`](Self::reply)；未回复即丢弃时，请求方得到 `None`。
+#[derive(Debug)]
+pub struct Replier<R>(channel::Sender<R>);
+
+/// 请求方等待回复的一端
+#[derive(Debug)]
+pub struct Reply<R>(channel::Receiver<R>);
+
+impl<C> Clone for CommandSender<C> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            notify: self.notify.clone(),
+            pending: self.pending.clone(),
+            capacity: self.capacity,
+            room: self.room.clone(),
+        }
+    }
+}
+
+/// 建立向设备发送指令的通道，`capacity` 限制异步发送时排队的指令数
+pub(super) fn command_channel<C>(
+    notify: CommandNotify,
+    capacity: usize,
+) -> (CommandSender<C>, CommandReceiver<C>) {
+    let (sender, receiver) = mpsc::channel();
+    let pending = Arc::new(AtomicUsize::new(0));
+    let room = channel::bounded(1);
+    (
+        CommandSender {
+            sender,
+            notify,
+            pending: pending.clone(),
+            capacity,
+            room: room.clone(),
+        },
+        CommandReceiver {
+            receiver,
+            pending,
+            room: room.0,
+        },
+    )
+}
+
+impl<C> CommandSender<C> {
+    /// 向设备发送指令，设备已断连时交还指令
+    #[inline]
+    pub fn send(&self, command: C) -> Result<(), mpsc::SendError<C>> {
+        // 先计数，以免设备线程先取走指令
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        self.push(command)
+    }
+
+    /// 送出已经计数的指令，失败时撤销计数
+    fn push(&self, command: C) -> Result<(), mpsc::SendError<C>> {
+        if let Err(e) = self.sender.send(command) {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+            return Err(e);
+        }
+        self.notify.0.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// 异步地向设备发送指令，排队的指令达到容量时等待设备取走指令
+    ///
+    /// 容量由 [`with_command_capacity`](super::SupervisorForMultiple::with_command_capacity) 设置。
+    /// 同步的 [`send`](Self::send) 不等待，但发送的指令同样占用容量。
+    pub async fn send_async(&self, command: C) -> Result<(), mpsc::SendError<C>> {
+        // 检查容量的同时占用空位，以免并发的发送越过容量
+        loop {
+            let pending = self.pending();
+            if pending >= self.capacity {
+                // 设备线程已退出
+                if self.room.1.recv().await.is_err() {
+                    return Err(mpsc::SendError(command));
+                }
+            } else if self
+                .pending
+                .compare_exchange_weak(pending, pending + 1, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+        }
+        self.push(command)?;
+        // 仍有空位，唤醒下一个等待者
+        if self.pending() < self.capacity {
+            let _ = self.room.0.try_send(());
+        }
+        Ok(())
+    }
+
+    /// 已发送但尚未交给驱动的指令数
+    ///
+    /// 指令只在设备产生事件或响应通知时交给驱动，持续增长说明设备可能已经停滞。
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// 向设备发送一个需要回复的请求
+    ///
+    /// `f` 将回复端包装为指令。同一设备的指令按发送顺序交给驱动，
+    /// 但回复的顺序由驱动决定：设备协议不区分请求时，驱动应按收到指令的顺序依次回复。
+    pub fn request<R>(
+        &self,
+        f: impl FnOnce(Replier<R>) -> C,
+    ) -> Result<Reply<R>, mpsc::SendError<C>> {
+        let (sender, receiver) = channel::bounded(1);
+        self.send(f(Replier(sender)))?;
+        Ok(Reply(receiver))
+    }
+}
+
+impl<K, C> Clone for CommandRouter<K, C> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K, C> Default for CommandRouter<K, C> {
+    #[inline]
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl<K: Eq + Hash, C> CommandRouter<K, C> {
+    /// 向 `key` 对应的设备发送指令
+    pub fn send(&self, key: &K, command: C) -> Result<(), NotConnected<C>> {
+        match self.0.lock().unwrap().get(key) {
+            Some(sender) => sender.send(command).map_err(|e| NotConnected(e.0)),
+            None => Err(NotConnected(command)),
+        }
+    }
+
+    /// `key` 对应的设备已发送但尚未交给驱动的指令数，设备不在线时返回 `None`
+    #[inline]
+    pub fn pending(&self, key: &K) -> Option<usize> {
+        self.0.lock().unwrap().get(key).map(CommandSender::pending)
+    }
+
+    /// 异步地向 `key` 对应的设备发送指令，见 [`CommandSender::send_async`]
+    pub async fn send_async(&self, key: &K, command: C) -> Result<(), NotConnected<C>> {
+        let sender = self.0.lock().unwrap().get(key).cloned();
+        match sender {
+            Some(sender) => sender
+                .send_async(command)
+                .await
+                .map_err(|e| NotConnected(e.0)),
+            None => Err(NotConnected(command)),
+        }
+    }
+
+    /// 判断 `key` 对应的设备是否在线
+    #[inline]
+    pub fn is_connected(&self, key: &K) -> bool {
+        self.0.lock().unwrap().contains_key(key)
+    }
+
+    /// 所有在线设备的键
+    #[inline]
+    pub fn keys(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.0.lock().unwrap().keys().cloned().collect()
+    }
+
+    #[inline]
+    pub(super) fn insert(&self, key: K, sender: CommandSender<C>) {
+        self.0.lock().unwrap().insert(key, sender);
+    }
+
+    #[inline]
+    pub(super) fn remove(&self, key: &K) {
+        self.0.lock().unwrap().remove(key);
+    }
+
+    #[inline]
+    pub(super) fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl<C> CommandReceiver<C> {
+    /// 取出所有待处理的指令
+    pub fn drain(&self, mut f: impl FnMut(C)) {
+        let mut any = false;
+        while let Ok(c) = self.receiver.try_recv() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+            any = true;
+            f(c);
+        }
+        if any {
+            let _ = self.room.try_send(());
+        }
+    }
+}
+
+impl<C> Drop for CommandReceiver<C> {
+    #[inline]
+    fn drop(&mut self) {
+        self.room.close();
+    }
+}
+
+impl<R> Replier<R> {
+    /// 回复请求，请求方已放弃等待时交还回复
+    #[inline]
+    pub fn reply(self, r: R) -> Result<(), R> {
+        self.0.try_send(r).map_err(|e| e.into_inner())
+    }
+}
+
+impl<R> Reply<R> {
+    /// 等待回复，驱动未回复就丢弃了回复端时返回 `None`
+    #[inline]
+    pub async fn recv(self) -> Option<R> {
+        self.0.recv().await.ok()
+    }
+
+    /// 如果已经收到回复，取出回复
+    #[inline]
+    pub fn try_recv(&self) -> Option<R> {
+        self.0.try_recv().ok()
+    }
+}
+
+impl CommandNotify {
+    /// 判断是否有待处理的指令
+    #[inline]
+    pub fn is_pending(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// 清除通知，返回清除前是否有待处理的指令
+    #[inline]
+    pub(super) fn take(&self) -> bool {
+        self.0.swap(false, Ordering::AcqRel)
+    }
+}
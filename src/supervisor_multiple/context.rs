@@ -1,23 +1,104 @@
-﻿use super::{SupervisorEventForMultiple, SupervisorForMultiple};
-use crate::{Driver, MultipleDeviceDriver};
+﻿use super::{
+    command::command_channel, CommandNotify, CommandRouter, CommandSender, DisconnectReason,
+    JoinReport, SupervisorEventForMultiple, SupervisorForMultiple,
+};
+use crate::{
+    open_keys,
+    trace::{debug, trace, warning},
+    Driver, Metrics, MultipleDeviceDriver, PacemakerHandle, RateMonitor, RejectReason, Stop,
+};
 use async_std::{
     channel::{self, Receiver, Sender, TryRecvError},
+    future,
+    prelude::FutureExt,
     task::{self, block_on, JoinHandle},
 };
-use std::{collections::HashMap, hash::Hash, sync::mpsc, time::Instant};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc},
+    thread,
+    time::Instant,
+};
 
-type Handle<D> = (
-    mpsc::Sender<<D as MultipleDeviceDriver>::Command>,
-    JoinHandle<Option<(<D as Driver>::Key, Box<D>)>>,
+/// 在线设备的句柄
+struct Handle<D: MultipleDeviceDriver> {
+    /// 向设备线程发送指令
+    sender: CommandSender<D::Command>,
+    /// 设备线程，退出时交还驱动对象，发生恐慌时返回错误，监控结束时取出
+    task: Option<DeviceTask<D>>,
+    /// 最后一次收到设备事件的时刻
+    last_event: Instant,
+    /// 放弃设备，设备线程将在下次回调时退出
+    abandon: Stop,
+    /// 设备的起搏器，与设备线程共享
+    pacemaker: Option<PacemakerHandle<<D as Driver>::Pacemaker>>,
+    /// 检查采样间隔，设置了期望周期时启用
+    rate: Option<RateMonitor>,
+    /// 通知设备线程事件已处理，要求回调后送出指令时启用
+    ack: Option<Sender<()>>,
+    /// 设备的唯一标识，用于与新打开的设备去重
+    identity: Option<String>,
+}
+
+/// 回收的驱动对象及其起搏器
+type Saved<D> = (
+    <D as Driver>::Key,
+    Box<D>,
+    Option<PacemakerHandle<<D as Driver>::Pacemaker>>,
 );
 
+/// 设备线程的结果
+type DeviceTask<D> = JoinHandle<thread::Result<Option<Saved<D>>>>;
+
+impl<D: MultipleDeviceDriver> Handle<D> {
+    /// 通知设备线程事件已处理
+    #[inline]
+    fn ack(&self) {
+        if let Some(ack) = &self.ack {
+            let _ = ack.try_send(());
+        }
+    }
+
+    /// 放弃设备并停止其起搏器，不必等待设备线程退出
+    #[inline]
+    fn abandon(&self) {
+        self.abandon.stop();
+        if let Some(p) = &self.pacemaker {
+            p.stop();
+        }
+    }
+}
+
 pub(super) struct JoinContextForMultiple<'a, D: MultipleDeviceDriver, F> {
     parent: &'a mut SupervisorForMultiple<D>,
     handles: HashMap<<D as Driver>::Key, Handle<D>>,
     sender: Sender<OutEvent<D>>,
     receiver: Receiver<OutEvent<D>>,
+    /// 暂停期间收到、恢复后处理的消息
+    deferred: VecDeque<OutEvent<D>>,
+    /// 通知所有设备线程退出并交还驱动对象
+    closing: Stop,
     target_len: usize,
     next_try: Instant,
+    attempts: usize,
+    /// 连续连接失败的次数达到上限，不再打开新的设备
+    abandoned: bool,
+    metrics: Option<Arc<Metrics>>,
+    router: Option<CommandRouter<D::Key, D::Command>>,
+    report: JoinReport<D::Key>,
+    /// 本次监控中连接过的设备
+    seen: HashSet<D::Key>,
+    /// 限制打开速率时，最近打开设备的时刻
+    spawned: VecDeque<Instant>,
+    /// 本次监控中在线设备数是否达到过目标
+    reached: bool,
+    /// 启用隔离时，各个设备最近断连的时刻
+    flaps: HashMap<D::Key, VecDeque<Instant>>,
+    /// 被隔离的设备及其隔离结束的时刻
+    quarantined: HashMap<D::Key, Instant>,
     f: F,
 }
 
@@ -27,88 +108,680 @@ where
     D::Key: Send + Clone + Eq + Hash,
     D::Event: Send,
     D::Command: Send,
-    F: FnMut(SupervisorEventForMultiple<D>) -> usize,
+    F: FnMut(SupervisorEventForMultiple<D>, usize) -> usize,
 {
     pub fn new(parent: &'a mut SupervisorForMultiple<D>, len: usize, f: F) -> Self {
         let (sender, receiver) = channel::unbounded();
-
-        // 取出上下文中保存的驱动对象
-        let handles = std::mem::take(&mut parent.0)
-            .into_iter()
-            .map(|(k, d)| (k.clone(), spawn(sender.clone(), k, d)))
-            .collect::<HashMap<_, _>>();
-
-        Self {
+        let metrics = parent.metrics.clone();
+        let router = parent.router.clone();
+        let next_try = parent.next_try.unwrap_or_else(Instant::now);
+        let mut context = Self {
             parent,
-            handles,
+            handles: HashMap::new(),
             sender,
             receiver,
+            deferred: VecDeque::new(),
+            closing: Stop::default(),
             target_len: len,
-            next_try: Instant::now(),
+            next_try,
+            attempts: 0,
+            abandoned: false,
+            metrics,
+            router,
+            report: Default::default(),
+            seen: HashSet::new(),
+            spawned: VecDeque::new(),
+            reached: false,
+            flaps: HashMap::new(),
+            quarantined: HashMap::new(),
             f,
+        };
+        // 取出上下文中保存的驱动对象
+        for (k, d, p) in std::mem::take(&mut context.parent.drivers) {
+            context.start(k, d, p);
         }
+        if let Some(m) = &context.metrics {
+            m.set_online(context.handles.len());
+        }
+        context.seen = context.handles.keys().cloned().collect();
+        context
     }
 
-    pub fn run(mut self) {
+    pub async fn run(mut self) -> JoinReport<D::Key> {
         use SupervisorEventForMultiple::*;
 
         // 尽量接收驱动的消息
         while self.target_len > 0 {
+            // 在线设备首次达到目标
+            if !self.reached && self.handles.len() >= self.wanted_len() {
+                self.reached = true;
+                let count = self.handles.len();
+                debug!(count, "target reached");
+                let len = (self.f)(TargetReached { count }, self.target_len);
+                self.set_target_len(len);
+                continue;
+            }
             // 接收消息
-            block_on(async { self.receive_from_child().await });
+            self.receive_from_child().await;
+            if self.parent.stop.is_stopped() {
+                self.set_target_len(0);
+                break;
+            }
+            if self.parent.pause.is_paused() {
+                continue;
+            }
+            self.release_quarantined();
+            if self.target_len == 0 {
+                break;
+            }
+            // 已放弃连接，只监控在线的设备，全部断连后结束
+            if self.abandoned {
+                if self.handles.is_empty() {
+                    self.set_target_len(0);
+                    break;
+                }
+                continue;
+            }
             // 设备数量不足时，尝试打开一些新的设备
-            let new = D::open_some(self.target_len - self.handles.len());
+            let len = self.wanted_len().saturating_sub(self.handles.len());
+            // 限制打开速率，额度用完时等到额度恢复
+            let len = match self.spawn_quota() {
+                Ok(quota) => len.min(quota),
+                Err(until) => {
+                    debug!(wait = ?until.saturating_duration_since(Instant::now()), "spawn rate limited");
+                    self.next_try = self.next_try.max(until);
+                    continue;
+                }
+            };
+            let cancel = self.parent.stop.clone();
+            let mut new = if self.quarantined.is_empty() {
+                task::spawn_blocking(move || D::open_some_cancellable(len, &cancel)).await
+            } else {
+                // 不打开被隔离的设备
+                let quarantined = self.quarantined.keys().cloned().collect::<HashSet<_>>();
+                task::spawn_blocking(move || {
+                    let keys = D::scan()
+                        .into_iter()
+                        .filter(|k| !quarantined.contains(k))
+                        .collect();
+                    open_keys(keys, len, 0, |_, _| true, &cancel, None)
+                })
+                .await
+            };
+            if self.parent.stop.is_stopped() {
+                // 取消前已经打开的设备回收到监控器中
+                for (k, d, p) in new {
+                    self.reclaim(k, d, Some(p));
+                }
+                self.set_target_len(0);
+                break;
+            }
+            if self.parent.spawn_rate.is_some() {
+                let now = Instant::now();
+                self.spawned.extend(std::iter::repeat_n(now, new.len()));
+            }
+            // 与在线设备标识相同的设备不再保存
+            new.retain_mut(|(k, d, p)| {
+                let duplicate = d.identity().is_some_and(|id| {
+                    self.handles
+                        .values()
+                        .any(|h| h.identity.as_ref() == Some(&id))
+                });
+                if duplicate {
+                    debug!(key = %crate::trace::key_tag::<D>(k), "duplicate identity");
+                    p.stop();
+                    d.shutdown();
+                    D::rejected(k, RejectReason::Duplicate);
+                }
+                !duplicate
+            });
             if new.is_empty() {
                 // 没能打开任何设备，报告
-                self.target_len = (self.f)(ConnectFailed {
-                    current: self.handles.len(),
-                    target: self.target_len,
-                    next_try: &mut self.next_try,
-                });
+                self.report.connect_failures += 1;
+                if let Some(m) = &self.metrics {
+                    m.connect_failed();
+                }
+                self.attempts += 1;
+                let attempts = self.attempts;
+                if self
+                    .parent
+                    .max_connect_attempts
+                    .is_some_and(|max| attempts >= max)
+                {
+                    debug!(attempts, "connect abandoned");
+                    self.abandoned = true;
+                    let len = (self.f)(ConnectAbandoned, self.target_len);
+                    self.set_target_len(len);
+                    continue;
+                }
+                let len = (self.f)(
+                    ConnectFailed {
+                        current: self.handles.len(),
+                        target: self.target_len,
+                        next_try: &mut self.next_try,
+                    },
+                    self.target_len,
+                );
+                debug!(
+                    current = self.handles.len(),
+                    next_try = ?self.next_try.saturating_duration_since(Instant::now()),
+                    "connect failed"
+                );
+                self.set_target_len(len);
             } else {
                 // 打开了一些设备，报告
                 // 所有已打开的设备都要保存到上下文
-                for (k, mut d) in new.into_iter() {
+                self.attempts = 0;
+                let mut opened = new.len();
+                for (k, mut d, p) in new.into_iter() {
+                    let full = self
+                        .parent
+                        .max_devices
+                        .is_some_and(|max| self.handles.len() >= max);
+                    if self.target_len > 0 && full {
+                        // 在线设备已达上限，多打开的设备直接关闭
+                        debug!(key = %crate::trace::key_tag::<D>(&k), "device limit reached");
+                        p.stop();
+                        d.shutdown();
+                        D::rejected(&k, RejectReason::Surplus);
+                        opened -= 1;
+                        continue;
+                    }
+                    self.report.connects += 1;
+                    if let Some(m) = &self.metrics {
+                        m.connected();
+                    }
+                    debug!(key = %crate::trace::key_tag::<D>(&k), "connected");
                     if self.target_len > 0 {
-                        self.target_len = (self.f)(Connected(&k, &mut d));
+                        // 本次监控中连接过的设备重新连接
+                        let event = if self.seen.insert(k.clone()) {
+                            Connected(&k, &mut *d)
+                        } else {
+                            Reconnected(&k, &mut *d)
+                        };
+                        let len = (self.f)(event, self.target_len);
+                        self.set_target_len(len);
                     }
                     if self.target_len > 0 {
-                        self.handles
-                            .insert(k.clone(), spawn(self.sender.clone(), k, d));
+                        self.start(k, d, Some(p));
                     } else {
-                        self.parent.0.push((k, d));
+                        self.reclaim(k, d, Some(p));
                     }
                 }
+                if let Some(m) = &self.metrics {
+                    m.set_online(self.handles.len());
+                }
+                debug!(requested = len, opened, "probe round");
+                if self.target_len > 0 {
+                    let requested = len;
+                    let len = (self.f)(ProbeRound { requested, opened }, self.target_len);
+                    self.set_target_len(len);
+                }
             }
         }
 
         // 结束所有线程，回收驱动对象并保存到上下文
-        std::mem::drop(self.receiver);
-        self.parent.0.extend(
-            self.handles
-                .into_iter()
-                .filter_map(|(_, (sender, handle))| {
-                    std::mem::drop(sender);
-                    task::block_on(handle)
-                }),
+        // 取出仍在监控的设备线程，被放弃的设备线程可能永不退出，不再等待
+        let tasks = self
+            .handles
+            .iter_mut()
+            .filter_map(|(k, h)| Some((k.clone(), h.task.take()?)))
+            .collect::<Vec<_>>();
+        let mut joined = Box::pin(async move {
+            let mut results = Vec::with_capacity(tasks.len());
+            for (k, task) in tasks {
+                results.push((k, task.await));
+            }
+            results
+        });
+        let results = if self.parent.drain_on_exit {
+            // 通知设备线程退出，直到这些线程都退出前继续处理通道中的消息
+            self.closing.stop();
+            debug!(online = self.handles.len(), "draining");
+            let mut results = None;
+            loop {
+                let e = match self.deferred.pop_front() {
+                    Some(e) => e,
+                    // 线程都已退出，处理完已到达的消息
+                    None if results.is_some() => match self.receiver.try_recv() {
+                        Ok(e) => e,
+                        Err(_) => break,
+                    },
+                    None => {
+                        let received = async { Some(self.receiver.recv().await) }
+                            .race(async {
+                                results = Some(joined.as_mut().await);
+                                None
+                            })
+                            .await;
+                        match received {
+                            Some(Ok(e)) => e,
+                            Some(Err(_)) => panic!("Impossible!"), // Self 里存了一个 Sender
+                            None => continue,
+                        }
+                    }
+                };
+                match e {
+                    OutEvent::Event(which, what) => {
+                        if let Some(handle) = self.handles.get(&which) {
+                            if let Some(m) = &self.metrics {
+                                m.event();
+                            }
+                            (self.f)(Event(which, what, &handle.sender), 0);
+                            handle.ack();
+                        }
+                    }
+                    OutEvent::Disconnected(which, driver, reason) => {
+                        if self.handles.remove(&which).is_some() {
+                            self.report.disconnects += 1;
+                            if let Some(m) = &self.metrics {
+                                m.disconnected();
+                            }
+                            (self.f)(Disconnected(which, Some(driver), reason), 0);
+                        }
+                    }
+                    OutEvent::Panicked(which) => {
+                        if self.handles.remove(&which).is_some() {
+                            warning!(
+                                key = %crate::trace::key_tag::<D>(&which),
+                                "device thread panicked"
+                            );
+                            self.report.disconnects += 1;
+                            self.report.panicked.push(which.clone());
+                            if let Some(m) = &self.metrics {
+                                m.disconnected();
+                            }
+                            (self.f)(Disconnected(which, None, DisconnectReason::Panicked), 0);
+                        }
+                    }
+                    OutEvent::CommandDropped(which, command) => {
+                        (self.f)(CommandDropped(which, command), 0);
+                    }
+                }
+            }
+            results.unwrap()
+        } else {
+            Vec::new()
+        };
+        // 关闭通道，设备线程发送消息失败后随之退出
+        self.receiver.close();
+        if let Some(m) = &self.metrics {
+            m.set_online(0);
+        }
+        if let Some(r) = &self.router {
+            r.clear();
+        }
+        // 先丢弃句柄的其他部分，以免设备线程等待通知
+        let remaining = std::mem::take(&mut self.handles)
+            .into_keys()
+            .collect::<HashSet<_>>();
+        let results = if self.parent.drain_on_exit {
+            results
+        } else {
+            joined.await
+        };
+        // 排空期间已报告断连的设备不再回收
+        for (k, result) in results {
+            if !remaining.contains(&k) {
+                continue;
+            }
+            match result {
+                Ok(Some((k, d, p))) => self.reclaim(k, d, p),
+                Ok(None) => {
+                    self.report.lost.push(k.clone());
+                    (self.f)(ReclaimFailed(k), 0);
+                }
+                Err(_) => {
+                    warning!(key = %crate::trace::key_tag::<D>(&k), "device thread panicked");
+                    self.report.panicked.push(k.clone());
+                    (self.f)(ReclaimFailed(k), 0);
+                }
+            }
+        }
+        // 保存重试的时机，下次监控时沿用
+        self.parent.next_try = Some(self.next_try);
+        if let Some(m) = &self.metrics {
+            m.set_next_try(None);
+        }
+        self.report
+    }
+
+    /// 更新目标设备数
+    #[inline]
+    fn set_target_len(&mut self, len: usize) {
+        if len != self.target_len {
+            debug!(from = self.target_len, to = len, "target len changed");
+            self.target_len = len;
+        }
+    }
+
+    /// 刷新设备的存活计时并检查采样间隔
+    ///
+    /// 空闲时以 `None` 调用回调不代表设备仍在产生数据，不刷新。
+    fn record_event(&mut self, which: &D::Key, t: Option<Instant>) {
+        use SupervisorEventForMultiple::*;
+
+        let Some(t) = t else {
+            return;
+        };
+        let handle = match self.handles.get_mut(which) {
+            Some(h) => h,
+            None => return,
+        };
+        handle.last_event = Instant::now();
+        trace!(key = %crate::trace::key_tag::<D>(which), "event");
+        if let Some(m) = &self.metrics {
+            m.event();
+        }
+        if let Some(rate) = &mut handle.rate {
+            if let Some(missed) = rate.record(t) {
+                debug!(
+                    key = %crate::trace::key_tag::<D>(which),
+                    missed, "samples missed"
+                );
+                if let Some(m) = &self.metrics {
+                    m.missed(missed);
+                }
+                let key = which.clone();
+                let len = (self.f)(
+                    Gap {
+                        key,
+                        missed,
+                        monitor: rate,
+                    },
+                    self.target_len,
+                );
+                self.set_target_len(len);
+            }
+        }
+    }
+
+    /// 从通道中取出 `which` 已经到达的后续事件，与 `first` 合并为一批
+    ///
+    /// 其他消息留待之后处理，遇到 `which` 断连时停止。
+    fn collect_batch(
+        &mut self,
+        which: &D::Key,
+        first: (Instant, D::Event),
+    ) -> Vec<(Instant, D::Event)> {
+        let mut batch = vec![first];
+        while let Ok(e) = self.receiver.try_recv() {
+            match e {
+                OutEvent::Event(k, what) if k == *which => {
+                    self.record_event(which, what.as_ref().map(|(t, _)| *t));
+                    batch.extend(what);
+                }
+                OutEvent::Disconnected(ref k, ..) | OutEvent::Panicked(ref k) if k == which => {
+                    self.deferred.push_back(e);
+                    break;
+                }
+                e => self.deferred.push_back(e),
+            }
+        }
+        batch
+    }
+
+    /// 启用隔离时记录设备断连，频繁断连的设备开始隔离
+    fn record_flap(&mut self, which: &D::Key) {
+        use SupervisorEventForMultiple::*;
+
+        let Some((n, window, cooldown)) = self.parent.quarantine else {
+            return;
+        };
+        let now = Instant::now();
+        let flaps = self.flaps.entry(which.clone()).or_default();
+        while flaps
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= window)
+        {
+            flaps.pop_front();
+        }
+        flaps.push_back(now);
+        if flaps.len() < n {
+            return;
+        }
+        self.flaps.remove(which);
+        let until = now + cooldown;
+        debug!(
+            key = %crate::trace::key_tag::<D>(which),
+            ?cooldown,
+            "device quarantined"
         );
+        self.quarantined.insert(which.clone(), until);
+        let key = which.clone();
+        let len = (self.f)(Quarantined { key, until }, self.target_len);
+        self.set_target_len(len);
+    }
+
+    /// 解除隔离期满的设备
+    fn release_quarantined(&mut self) {
+        use SupervisorEventForMultiple::*;
+
+        let now = Instant::now();
+        let expired = self
+            .quarantined
+            .iter()
+            .filter(|(_, until)| now >= **until)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for which in expired {
+            self.quarantined.remove(&which);
+            debug!(key = %crate::trace::key_tag::<D>(&which), "quarantine ended");
+            let len = (self.f)(QuarantineEnded(which), self.target_len);
+            self.set_target_len(len);
+        }
+    }
+
+    /// 本轮还能打开的设备数，额度用完时返回额度恢复的时刻
+    fn spawn_quota(&mut self) -> Result<usize, Instant> {
+        let Some((n, interval)) = self.parent.spawn_rate else {
+            return Ok(usize::MAX);
+        };
+        let now = Instant::now();
+        while self
+            .spawned
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= interval)
+        {
+            self.spawned.pop_front();
+        }
+        match n.saturating_sub(self.spawned.len()) {
+            0 => Err(self.spawned[self.spawned.len() - n] + interval),
+            quota => Ok(quota),
+        }
+    }
+
+    /// 需要同时在线的设备数，不超过设备数上限
+    #[inline]
+    fn wanted_len(&self) -> usize {
+        match self.parent.max_devices {
+            Some(max) => self.target_len.min(max),
+            None => self.target_len,
+        }
+    }
+
+    /// 在线设备不足且未放弃连接，需要打开新的设备
+    #[inline]
+    fn short(&self) -> bool {
+        !self.abandoned && self.handles.len() < self.wanted_len()
+    }
+
+    /// 为设备启动线程并保存句柄
+    ///
+    /// 无法创建线程时报告设备断连，驱动对象回收到监控器中，下次监控时重试。
+    fn start(&mut self, k: D::Key, d: Box<D>, p: Option<PacemakerHandle<D::Pacemaker>>) {
+        use SupervisorEventForMultiple::*;
+
+        let sender = self.sender.clone();
+        let closing = self.closing.clone();
+        match spawn(self.parent, sender, k.clone(), d, p.clone(), closing) {
+            Ok(handle) => self.insert_handle(k, handle),
+            Err((d, _e)) => {
+                warning!(key = %crate::trace::key_tag::<D>(&k), error = %_e, "failed to spawn device thread");
+                self.report.disconnects += 1;
+                if let Some(m) = &self.metrics {
+                    m.disconnected();
+                }
+                let len = (self.f)(
+                    Disconnected(k.clone(), None, DisconnectReason::SpawnFailed),
+                    self.target_len,
+                );
+                self.set_target_len(len);
+                self.reclaim(k, d, p);
+            }
+        }
+    }
+
+    /// 将驱动对象回收到监控器中，记入总结并报告
+    fn reclaim(&mut self, k: D::Key, d: Box<D>, p: Option<PacemakerHandle<D::Pacemaker>>) {
+        self.report.reclaimed.push(k.clone());
+        self.parent.drivers.push((k.clone(), d, p));
+        (self.f)(SupervisorEventForMultiple::Reclaimed(k), 0);
+    }
+
+    /// 保存在线设备的句柄，同步到指令路由
+    fn insert_handle(&mut self, k: D::Key, mut handle: Handle<D>) {
+        handle.rate = self.parent.expected_period.map(RateMonitor::new);
+        if let Some(r) = &self.router {
+            r.insert(k.clone(), handle.sender.clone());
+        }
+        self.handles.insert(k, handle);
+    }
+
+    /// 移除在线设备的句柄，同步到指令路由
+    fn remove_handle(&mut self, k: &D::Key) -> Option<Handle<D>> {
+        if let Some(r) = &self.router {
+            r.remove(k);
+        }
+        self.handles.remove(k)
+    }
+
+    /// 最早一个设备超时的时刻
+    fn liveness_deadline(&self) -> Option<Instant> {
+        let timeout = self.parent.liveness_timeout?;
+        self.handles.values().map(|h| h.last_event + timeout).min()
+    }
+
+    /// 将超时未产生事件的设备视作断连
+    fn check_liveness(&mut self) {
+        use SupervisorEventForMultiple::*;
+
+        let timeout = match self.parent.liveness_timeout {
+            Some(t) => t,
+            None => return,
+        };
+        let now = Instant::now();
+        let silent = self
+            .handles
+            .iter()
+            .filter(|(_, h)| now >= h.last_event + timeout)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for which in silent {
+            if self.target_len == 0 {
+                return;
+            }
+            if let Some(handle) = self.remove_handle(&which) {
+                handle.abandon();
+            }
+            debug!(
+                key = %crate::trace::key_tag::<D>(&which),
+                "silent device disconnected"
+            );
+            self.record_flap(&which);
+            self.report.disconnects += 1;
+            if let Some(m) = &self.metrics {
+                m.disconnected();
+                m.set_online(self.handles.len());
+            }
+            let len = (self.f)(
+                Disconnected(which, None, DisconnectReason::Silent),
+                self.target_len,
+            );
+            self.set_target_len(len);
+        }
+    }
+
+    /// 暂停期间保持设备在线，等待恢复
+    async fn wait_resume(&mut self) {
+        let pause = self.parent.pause.clone();
+        debug!("paused");
+        if self.parent.buffer_while_paused {
+            // 消息留在通道中，恢复后依次处理
+            pause.resumed().await;
+        } else {
+            // 丢弃设备事件，其他消息留到恢复后处理
+            loop {
+                let received = async { Some(self.receiver.recv().await) }
+                    .race(async {
+                        pause.resumed().await;
+                        None
+                    })
+                    .await;
+                match received {
+                    Some(Ok(OutEvent::Event(which, _))) => {
+                        if let Some(handle) = self.handles.get(&which) {
+                            handle.ack();
+                        }
+                    }
+                    Some(Ok(e)) => self.deferred.push_back(e),
+                    Some(Err(_)) => panic!("Impossible!"), // 就算没有任何设备在线，Self 里也存了一个 Sender
+                    None => break,
+                }
+            }
+        }
+        // 暂停期间不检查存活，恢复时重新计时
+        let now = Instant::now();
+        for h in self.handles.values_mut() {
+            h.last_event = now;
+        }
+        debug!("resumed");
     }
 
     /// 从线程中接收消息
     async fn receive_from_child(&mut self) {
         use SupervisorEventForMultiple::*;
 
-        while self.target_len > 0 {
+        loop {
+            if self.parent.stop.is_stopped() {
+                return;
+            }
+            if self.parent.pause.is_paused() {
+                self.wait_resume().await;
+            }
+            self.check_liveness();
+            if self.target_len == 0 {
+                return;
+            }
+            // 设备不足时公开重试的时机
+            if let Some(m) = &self.metrics {
+                m.set_next_try(self.short().then_some(self.next_try));
+            }
             let wait = self.next_try.checked_duration_since(Instant::now());
-            let event = if self.handles.is_empty() {
+            let event = if let Some(e) = self.deferred.pop_front() {
+                e
+            } else if self.handles.is_empty() {
                 // 没有任何在线的设备了，等待到重试的时机并退出
                 if let Some(dur) = wait {
                     task::sleep(dur).await;
                 }
                 return;
-            } else if wait.is_some() || self.handles.len() >= self.target_len {
-                // 还不到重试的时候或已有足够多设备在线，等待所有消息
-                match self.receiver.recv().await {
+            } else if wait.is_some() || !self.short() {
+                // 还不到重试的时候、已有足够多设备在线或已放弃连接，等待所有消息
+                // 设置了存活超时则最多等到第一个设备超时，设备不足则最多等到重试的时机
+                let retry = self.short().then_some(self.next_try);
+                let deadline = self.liveness_deadline().into_iter().chain(retry).min();
+                let received = match deadline {
+                    Some(deadline) => {
+                        let dur = deadline.saturating_duration_since(Instant::now());
+                        match future::timeout(dur, self.receiver.recv()).await {
+                            Ok(r) => r,
+                            Err(_) => continue,
+                        }
+                    }
+                    None => self.receiver.recv().await,
+                };
+                match received {
                     Ok(e) => e,
                     Err(_) => panic!("Impossible!"), // 就算没有任何设备在线，Self 里也存了一个 Sender
                 }
@@ -120,53 +793,261 @@ where
                     Err(TryRecvError::Closed) => panic!("Impossible!"), // 就算没有任何设备在线，Self 里也存了一个 Sender
                 }
             };
-            self.target_len = match event {
+            let len = match event {
                 // 一般事件
                 OutEvent::Event(which, what) => {
-                    let sender = &self.handles.get(&which).unwrap().0;
-                    (self.f)(Event(which, what, sender))
+                    // 已被视作断连的设备，丢弃其残留的事件
+                    if !self.handles.contains_key(&which) {
+                        continue;
+                    }
+                    self.record_event(&which, what.as_ref().map(|(t, _)| *t));
+                    match what {
+                        // 合并通道中同一设备的后续事件，不越过暂停期间缓存的事件
+                        Some(e) if self.parent.batch_events && self.deferred.is_empty() => {
+                            let batch = self.collect_batch(&which, e);
+                            let handle = &self.handles[&which];
+                            let len =
+                                (self.f)(EventBatch(which, batch, &handle.sender), self.target_len);
+                            handle.ack();
+                            len
+                        }
+                        what => {
+                            let handle = &self.handles[&which];
+                            let len = (self.f)(Event(which, what, &handle.sender), self.target_len);
+                            handle.ack();
+                            len
+                        }
+                    }
                 }
                 // 有设备断连
-                OutEvent::Disconnected(which) => {
-                    self.handles.remove(&which);
-                    (self.f)(Disconnected(which))
+                OutEvent::Disconnected(which, driver, reason) => {
+                    if self.remove_handle(&which).is_none() {
+                        continue;
+                    }
+                    debug!(key = %crate::trace::key_tag::<D>(&which), "disconnected");
+                    self.record_flap(&which);
+                    self.report.disconnects += 1;
+                    if let Some(m) = &self.metrics {
+                        m.disconnected();
+                        m.set_online(self.handles.len());
+                    }
+                    (self.f)(Disconnected(which, Some(driver), reason), self.target_len)
                 }
-            }
+                // 设备线程发生恐慌
+                OutEvent::Panicked(which) => {
+                    if self.remove_handle(&which).is_none() {
+                        continue;
+                    }
+                    warning!(
+                        key = %crate::trace::key_tag::<D>(&which),
+                        "device thread panicked"
+                    );
+                    self.record_flap(&which);
+                    self.report.disconnects += 1;
+                    self.report.panicked.push(which.clone());
+                    if let Some(m) = &self.metrics {
+                        m.disconnected();
+                        m.set_online(self.handles.len());
+                    }
+                    (self.f)(
+                        Disconnected(which, None, DisconnectReason::Panicked),
+                        self.target_len,
+                    )
+                }
+                // 指令未能按时送达
+                OutEvent::CommandDropped(which, command) => {
+                    debug!(key = %crate::trace::key_tag::<D>(&which), "command dropped");
+                    (self.f)(CommandDropped(which, command), self.target_len)
+                }
+            };
+            self.set_target_len(len);
         }
     }
 }
 
-enum OutEvent<D: Driver> {
+enum OutEvent<D: MultipleDeviceDriver> {
     Event(D::Key, Option<(Instant, D::Event)>),
-    Disconnected(D::Key),
+    Disconnected(D::Key, Box<D>, DisconnectReason),
+    Panicked(D::Key),
+    CommandDropped(D::Key, D::Command),
 }
 
+/// 在新线程中监听设备事件，线程的设置取自 `parent`
+///
+/// 系统无法创建线程时交还驱动对象。
 fn spawn<D: MultipleDeviceDriver>(
+    parent: &SupervisorForMultiple<D>,
     sender: Sender<OutEvent<D>>,
     k: D::Key,
     mut d: Box<D>,
-) -> Handle<D>
+    pacemaker: Option<PacemakerHandle<D::Pacemaker>>,
+    closing: Stop,
+) -> Result<Handle<D>, (Box<D>, io::Error)>
 where
-    D::Key: Send + Clone,
+    D::Key: Send + Clone + Hash,
     D::Event: Send,
     D::Command: Send,
 {
-    let (command_sender, command_receiver) = mpsc::channel();
-    (
-        command_sender,
-        task::spawn_blocking(move || {
-            let ok = d.join(|d, event| {
-                while let Ok(c) = command_receiver.try_recv() {
-                    d.send(c);
-                }
-                block_on(sender.send(OutEvent::Event(k.clone(), event))).is_ok()
+    let notify = CommandNotify::default();
+    d.set_command_notify(notify.clone());
+    let (command_sender, command_receiver) =
+        command_channel(notify.clone(), parent.command_capacity);
+    // 起搏器产生的保活指令经过指令通道送出，设备线程退出后停止
+    // 通道中仍有指令未送出时跳过，以免设备停滞时保活指令堆积
+    let failed = match &pacemaker {
+        Some(p) => {
+            let sender = command_sender.clone();
+            p.set_hook(move |p| {
+                if sender.pending() > 0 {
+                    return true;
+                }
+                match D::keepalive(p) {
+                    Some(c) => sender.send(c).is_ok(),
+                    None => true,
+                }
             });
-            if ok {
-                Some((k, d))
+            p.failure()
+        }
+        None => Stop::default(),
+    };
+    let abandon = Stop::default();
+    let abandoned = abandon.clone();
+    let builder = parent.thread_builder.as_ref().map(|f| f(&k));
+    let init = parent.thread_init.clone();
+    let middleware = parent.command_middleware.clone();
+    let (ack, acked) = if parent.flush_after_callback {
+        let (ack, acked) = channel::bounded(1);
+        (Some(ack), Some(acked))
+    } else {
+        (None, None)
+    };
+    let identity = d.identity();
+    #[cfg(feature = "tracing")]
+    let span = crate::trace::device_span::<D>(&k);
+    let shared_pacemaker = pacemaker.clone();
+    let run = move |mut d: Box<D>| {
+        #[cfg(feature = "tracing")]
+        let _span = span.entered();
+        let joined = panic::catch_unwind(AssertUnwindSafe(|| {
+            if let Some(init) = &init {
+                init(&k);
+            }
+            // 指令经过中间件后送出，未能按时送达的指令交给监控器
+            let timeout = D::command_timeout();
+            let deliver = |d: &mut D| {
+                command_receiver.drain(|c| {
+                    let c = match &middleware {
+                        Some(f) => match f(&k, c) {
+                            Some(c) => c,
+                            None => return,
+                        },
+                        None => c,
+                    };
+                    if let Err(c) = d.send_timeout(c, timeout) {
+                        let _ = block_on(sender.send(OutEvent::CommandDropped(k.clone(), c)));
+                    }
+                })
+            };
+            // 复位后尚未产生事件
+            let mut fresh = false;
+            loop {
+                let ok = d.join(|d, event| {
+                    // 监控器已放弃此设备、起搏器发送失败或监控即将结束
+                    if abandoned.is_stopped() || failed.is_stopped() || closing.is_stopped() {
+                        return false;
+                    }
+                    notify.take();
+                    deliver(d);
+                    if matches!(&event, Some((_, e)) if !d.filter_event(e)) {
+                        return true;
+                    }
+                    fresh &= event.is_none();
+                    if block_on(sender.send(OutEvent::Event(k.clone(), event))).is_err() {
+                        return false;
+                    }
+                    // 等待监控器处理完事件，立即送出回调中发送的指令
+                    if let Some(acked) = &acked {
+                        let _ = block_on(acked.recv());
+                        deliver(d);
+                    }
+                    true
+                });
+                // 驱动因错误退出，先尝试原地复位
+                if ok || fresh || abandoned.is_stopped() || failed.is_stopped() || !d.reset() {
+                    break ok;
+                }
+                debug!("device reset");
+                fresh = true;
+            }
+        }));
+        let ok = match joined {
+            Ok(ok) => ok,
+            // 发生恐慌，驱动对象的状态已不可信，直接丢弃
+            Err(e) => {
+                if let Some(p) = &pacemaker {
+                    p.stop();
+                }
+                if !abandoned.is_stopped() {
+                    let _ = block_on(sender.send(OutEvent::Panicked(k)));
+                }
+                return Err(e);
+            }
+        };
+        // 除非交还驱动对象，设备线程退出时停止起搏器
+        if abandoned.is_stopped() {
+            if let Some(p) = &pacemaker {
+                p.stop();
+            }
+            d.shutdown();
+            Ok(None)
+        } else if ok && !failed.is_stopped() {
+            Ok(Some((k, d, pacemaker)))
+        } else {
+            if let Some(p) = &pacemaker {
+                p.stop();
+            }
+            let reason = if ok {
+                DisconnectReason::PacemakerFailed
             } else {
-                let _ = block_on(sender.send(OutEvent::Disconnected(k)));
-                None
+                DisconnectReason::Exited
+            };
+            // 监控器已结束，无人接收驱动对象
+            if let Err(e) = block_on(sender.send(OutEvent::Disconnected(k, d, reason))) {
+                if let OutEvent::Disconnected(_, mut d, _) = e.into_inner() {
+                    d.shutdown();
+                }
             }
-        }),
-    )
+            Ok(None)
+        }
+    };
+    // 指定了线程设置时使用独立的线程，否则使用阻塞任务的线程池
+    let task = match builder {
+        Some(builder) => {
+            // 线程创建成功后才交出驱动对象，失败时驱动对象仍在此处
+            let (driver, given) = mpsc::sync_channel(1);
+            let (result, received) = channel::bounded(1);
+            let spawned = builder.spawn(move || {
+                if let Ok(d) = given.recv() {
+                    let _ = result.try_send(run(d));
+                }
+            });
+            if let Err(e) = spawned {
+                return Err((d, e));
+            }
+            let _ = driver.send(d);
+            // 线程未送出结果就退出，视作恐慌
+            task::spawn(async move { received.recv().await.unwrap_or_else(|e| Err(Box::new(e))) })
+        }
+        None => task::spawn_blocking(move || run(d)),
+    };
+    Ok(Handle {
+        identity,
+        sender: command_sender,
+        task: Some(task),
+        last_event: Instant::now(),
+        abandon,
+        pacemaker: shared_pacemaker,
+        rate: None,
+        ack,
+    })
 }
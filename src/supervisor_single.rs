@@ -1,8 +1,36 @@
-﻿use super::Driver;
-use std::time::Instant;
+﻿use super::{open_keys, Driver, Metrics, MultipleDeviceDriver, PacemakerHandle, Stop, Supervisor};
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+/// 等待在下一次回调时送给驱动的保活指令，至多一条
+type Keepalive<D> = Mutex<Option<Box<dyn FnOnce(&mut D) + Send>>>;
 
 /// 控制一个驱动程序的监控器
-pub struct SupervisorForSingle<D>(Option<Box<D>>);
+pub struct SupervisorForSingle<D: Driver> {
+    driver: Option<Box<D>>,
+    metrics: Option<Arc<Metrics>>,
+    stop: Stop,
+    max_connect_attempts: Option<usize>,
+    /// 每次尝试连接的时限
+    connect_timeout: Option<Duration>,
+    last_event: Option<Instant>,
+    /// 当前驱动的起搏器，断连时停止
+    pacemaker: Option<PacemakerHandle<D::Pacemaker>>,
+    /// 当前驱动的起搏器发送失败的标记
+    pacemaker_failed: Stop,
+    /// 连续尝试连接失败的次数
+    attempts: usize,
+    /// 指定连接的键，不为空时不枚举其他键
+    target: Option<D::Key>,
+    /// 最近一次连接的键
+    last_key: Option<D::Key>,
+    /// 当前驱动复位后尚未产生事件
+    fresh: bool,
+    /// 当前驱动的起搏器产生、尚未送出的保活指令
+    keepalive: Arc<Keepalive<D>>,
+}
 
 /// 监控一个驱动程序时产生的事件
 pub enum SupervisorEventForSingle<'a, D: Driver> {
@@ -10,25 +38,66 @@ pub enum SupervisorEventForSingle<'a, D: Driver> {
     Connected(<D as Driver>::Key, &'a mut D),
     /// 监听到驱动程序事件
     Event(&'a mut D, Option<(Instant, D::Event)>),
+    /// 重新连接到与上次不同的键，在新设备的 [`Connected`](Self::Connected) 之前产生
+    Reconnected {
+        old: <D as Driver>::Key,
+        new: <D as Driver>::Key,
+    },
     /// 断开连接
     Disconnected,
     /// 尝试连接但失败
     ConnectFailed,
+    /// 连续失败次数达到上限，放弃连接，监控随之结束
+    ConnectAbandoned,
+}
+
+/// [`SupervisorForSingle::step`] 的结果
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StepResult {
+    /// 成功连接到驱动程序
+    Connected,
+    /// 尝试连接但失败
+    ConnectFailed,
+    /// 连续失败次数达到上限，放弃连接
+    ConnectAbandoned,
+    /// 监听了一段时间的事件，驱动仍然在线
+    Polled,
+    /// 驱动断连
+    Disconnected,
+    /// 回调要求结束，或监控器已停止
+    Stopped,
 }
 
-impl<D> Default for SupervisorForSingle<D> {
+impl<D: Driver> Default for SupervisorForSingle<D> {
     /// 产生一个空的监控器
     #[inline]
     fn default() -> Self {
-        Self(None)
+        Self {
+            driver: None,
+            metrics: None,
+            stop: Default::default(),
+            max_connect_attempts: None,
+            connect_timeout: None,
+            last_event: None,
+            pacemaker: None,
+            pacemaker_failed: Default::default(),
+            attempts: 0,
+            target: None,
+            last_key: None,
+            fresh: false,
+            keepalive: Default::default(),
+        }
     }
 }
 
-impl<D> From<Box<D>> for SupervisorForSingle<D> {
+impl<D: Driver> From<Box<D>> for SupervisorForSingle<D> {
     /// 监控传入的驱动程序 `d`
     #[inline]
     fn from(d: Box<D>) -> Self {
-        Self(Some(d))
+        Self {
+            driver: Some(d),
+            ..Default::default()
+        }
     }
 }
 
@@ -36,44 +105,327 @@ impl<D: Driver> SupervisorForSingle<D> {
     /// 取出监控器中保存的驱动对象，取出后监控器为空
     #[inline]
     pub fn take(&mut self) -> Option<Box<D>> {
-        self.0.take()
+        self.driver.take()
+    }
+
+    /// 判断监控器中是否保存了驱动对象
+    ///
+    /// 只说明持有驱动对象，不代表设备正在产生事件。
+    #[inline]
+    pub fn is_connected(&self) -> bool {
+        self.driver.is_some()
+    }
+
+    /// 查看监控器中保存的驱动对象，不将其取出
+    #[inline]
+    pub fn peek(&self) -> Option<&D> {
+        self.driver.as_deref()
+    }
+
+    /// 设置连续尝试连接的次数上限，成功连接后重新计数
+    #[inline]
+    pub fn with_max_connect_attempts(mut self, n: usize) -> Self {
+        self.max_connect_attempts = Some(n);
+        self
+    }
+
+    /// 设置每次尝试连接的时限，时限短于 [`Driver::discover_timeout`] 时以它为准
+    ///
+    /// 超过时限仍未找到设备即视作连接失败，由回调决定是否继续尝试。
+    /// 正在验证的设备只在产生事件时检查时限。
+    #[inline]
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// 只连接 `key` 对应的设备，不枚举其他键
+    #[inline]
+    pub fn with_key(mut self, key: D::Key) -> Self {
+        self.target = Some(key);
+        self
+    }
+
+    /// 获取监控器的统计量，首次调用时启用统计
+    pub fn metrics(&mut self) -> Arc<Metrics> {
+        self.metrics.get_or_insert_with(Default::default).clone()
     }
 
+    /// 最后一次监听到驱动事件的时刻，不包括空闲时以 `None` 调用的回调
+    ///
+    /// 监控期间需要读取时，使用 [`metrics`](Self::metrics) 得到的共享统计量。
+    #[inline]
+    pub fn last_event(&self) -> Option<Instant> {
+        self.last_event
+    }
+
+    /// 距最后一次监听到驱动事件经过的时间
+    #[inline]
+    pub fn since_last_event(&self) -> Option<Duration> {
+        self.last_event.map(|t| t.elapsed())
+    }
+
+    /// 获取停止句柄，用于从其他线程结束 [`join`](Self::join)
+    ///
+    /// 监控器在每轮重连之间和每个驱动事件到来时检查句柄，正在进行的连接也将被取消，
+    /// 但驱动长时间不产生事件时无法立即打断。
+    #[inline]
+    pub fn stop_handle(&self) -> Stop {
+        self.stop.clone()
+    }
+}
+
+impl<D: Driver> SupervisorForSingle<D>
+where
+    D::Key: Send + Clone + PartialEq + 'static,
+{
     /// 使用监控器监控驱动程序
-    pub fn join<F>(&mut self, mut f: F)
+    #[inline]
+    pub fn join<F>(&mut self, f: F)
+    where
+        F: FnMut(SupervisorEventForSingle<D>) -> bool,
+    {
+        self.join_with(f, |_, _| {});
+    }
+
+    /// 执行一步监控，然后交还控制权
+    ///
+    /// 没有驱动时尝试连接一次；否则监听驱动事件，直到驱动退出或经过 `budget`。
+    /// 监听期间只在事件到来时检查时限，因此驱动长时间不产生事件时可能超出时限。
+    #[inline]
+    pub fn step<F>(&mut self, budget: Duration, f: F) -> StepResult
     where
         F: FnMut(SupervisorEventForSingle<D>) -> bool,
     {
+        self.step_with(budget, f, |_, _| {})
+    }
+
+    /// 监控驱动程序，每次连接后以 `hook` 为新的起搏器设置回调
+    fn join_with<F, H>(&mut self, mut f: F, hook: H)
+    where
+        F: FnMut(SupervisorEventForSingle<D>) -> bool,
+        H: Fn(&PacemakerHandle<D::Pacemaker>, Weak<Keepalive<D>>),
+    {
+        self.attempts = 0;
         loop {
-            use SupervisorEventForSingle::*;
-            // 取出上下文中保存的驱动
-            if let Some(mut driver) = self.0.take() {
-                // 驱动主动退出，保存并连锁退出
-                if driver.join(|d, e| f(Event(d, e))) {
-                    self.0 = Some(driver);
-                    return;
+            match self.step_with(Duration::MAX, &mut f, &hook) {
+                StepResult::Stopped | StepResult::ConnectAbandoned => return,
+                _ => {}
+            }
+        }
+    }
+
+    /// 执行一步监控，连接后以 `hook` 为新的起搏器设置回调
+    fn step_with<F, H>(&mut self, budget: Duration, mut f: F, hook: H) -> StepResult
+    where
+        F: FnMut(SupervisorEventForSingle<D>) -> bool,
+        H: FnOnce(&PacemakerHandle<D::Pacemaker>, Weak<Keepalive<D>>),
+    {
+        use SupervisorEventForSingle::*;
+
+        let metrics = self.metrics.clone();
+        let stop = self.stop.clone();
+        if stop.is_stopped() {
+            return StepResult::Stopped;
+        }
+        // 取出上下文中保存的驱动
+        if let Some(mut driver) = self.driver.take() {
+            if let Some(m) = &metrics {
+                m.set_online(1);
+            }
+            // 驱动主动退出，保存并连锁退出
+            // 起搏器发送失败视作断连
+            let failed = self.pacemaker_failed.clone();
+            let deadline = Instant::now().checked_add(budget);
+            let mut expired = false;
+            let exited = driver.join(|d, e| {
+                if failed.is_stopped() {
+                    return false;
                 }
-                // 驱动断联后不希望再次尝试
-                if !f(Disconnected) {
-                    return;
+                if deadline.is_some_and(|t| Instant::now() >= t) {
+                    expired = true;
+                    return false;
                 }
-            }
-            // 上下文中驱动已取出
-            match D::open_some(1).pop() {
-                // 成功打开驱动，保存
-                Some((t, driver)) => {
-                    self.0 = Some(driver);
-                    if !f(Connected(t, self.0.as_mut().unwrap())) {
-                        return;
+                if let Some(c) = self.keepalive.lock().unwrap().take() {
+                    c(d);
+                }
+                if matches!(&e, Some((_, e)) if !d.filter_event(e)) {
+                    return !stop.is_stopped();
+                }
+                // 空闲时以 None 调用回调不算作事件
+                if e.is_some() {
+                    self.last_event = Some(Instant::now());
+                    self.fresh = false;
+                    if let Some(m) = &metrics {
+                        m.event();
                     }
                 }
-                // 未能打开驱动
-                None => {
-                    if !f(ConnectFailed) {
-                        return;
+                !stop.is_stopped() && f(Event(d, e))
+            });
+            if let Some(m) = &metrics {
+                m.set_online(0);
+            }
+            if exited && !failed.is_stopped() {
+                self.driver = Some(driver);
+                return if expired {
+                    StepResult::Polled
+                } else {
+                    StepResult::Stopped
+                };
+            }
+            // 驱动因错误退出，先尝试原地复位
+            if !exited && !self.fresh && !failed.is_stopped() && driver.reset() {
+                self.fresh = true;
+                self.driver = Some(driver);
+                return StepResult::Polled;
+            }
+            if let Some(p) = self.pacemaker.take() {
+                p.stop();
+            }
+            driver.shutdown();
+            if let Some(m) = &metrics {
+                m.disconnected();
+            }
+            // 驱动断联后不希望再次尝试
+            return if f(Disconnected) {
+                StepResult::Disconnected
+            } else {
+                StepResult::Stopped
+            };
+        }
+        // 上下文中驱动已取出
+        // 停止句柄同时取消正在进行的连接
+        // 指定了键时只打开这个键
+        let deadline = self.connect_timeout.map(|t| Instant::now() + t);
+        let mut opened = match (&self.target, deadline) {
+            (None, None) => D::open_some_cancellable(1, &stop),
+            (target, _) => {
+                let keys = target.as_ref().map_or_else(D::scan, |t| vec![t.clone()]);
+                open_keys(keys, 1, 0, |_, _| true, &stop, deadline)
+            }
+        };
+        if stop.is_stopped() {
+            return StepResult::Stopped;
+        }
+        match opened.pop() {
+            // 成功打开驱动，保存
+            Some((t, driver, p)) => {
+                self.attempts = 0;
+                self.fresh = false;
+                self.pacemaker_failed = p.failure();
+                // 每次连接使用新的保活指令槽，之前的起搏器不再送入指令
+                self.keepalive = Default::default();
+                hook(&p, Arc::downgrade(&self.keepalive));
+                if let Some(m) = &metrics {
+                    m.connected();
+                }
+                self.pacemaker = Some(p);
+                self.driver = Some(driver);
+                // 连接到了不同的设备
+                if let Some(old) = self.last_key.replace(t.clone()) {
+                    if old != t
+                        && !f(Reconnected {
+                            old,
+                            new: t.clone(),
+                        })
+                    {
+                        return StepResult::Stopped;
                     }
                 }
+                if f(Connected(t, self.driver.as_mut().unwrap())) {
+                    StepResult::Connected
+                } else {
+                    StepResult::Stopped
+                }
+            }
+            // 未能打开驱动
+            None => {
+                if let Some(m) = &metrics {
+                    m.connect_failed();
+                }
+                self.attempts += 1;
+                if self
+                    .max_connect_attempts
+                    .is_some_and(|max| self.attempts >= max)
+                {
+                    f(ConnectAbandoned);
+                    StepResult::ConnectAbandoned
+                } else if f(ConnectFailed) {
+                    StepResult::ConnectFailed
+                } else {
+                    StepResult::Stopped
+                }
             }
         }
     }
 }
+
+impl<D: MultipleDeviceDriver> SupervisorForSingle<D>
+where
+    D::Key: Send + Clone + PartialEq + 'static,
+    D::Command: Send,
+{
+    /// 与 [`join`](Self::join) 相同，同时将起搏器产生的保活指令送给驱动，见 [`MultipleDeviceDriver::keepalive`]
+    ///
+    /// 保活指令在驱动下一次调用回调时送出，尚未送出时不产生新的保活指令。
+    #[inline]
+    pub fn join_with_keepalive<F>(&mut self, f: F)
+    where
+        F: FnMut(SupervisorEventForSingle<D>) -> bool,
+    {
+        self.join_with(f, Self::keepalive_hook);
+    }
+
+    /// 与 [`step`](Self::step) 相同，同时将起搏器产生的保活指令送给驱动，见 [`join_with_keepalive`](Self::join_with_keepalive)
+    #[inline]
+    pub fn step_with_keepalive<F>(&mut self, budget: Duration, f: F) -> StepResult
+    where
+        F: FnMut(SupervisorEventForSingle<D>) -> bool,
+    {
+        self.step_with(budget, f, Self::keepalive_hook)
+    }
+
+    /// 起搏器每次发送触发脉冲后，如果之前的保活指令已经送出，产生新的保活指令
+    fn keepalive_hook(p: &PacemakerHandle<D::Pacemaker>, slot: Weak<Keepalive<D>>) {
+        p.set_hook(move |p| {
+            let Some(slot) = slot.upgrade() else {
+                return false;
+            };
+            let mut slot = slot.lock().unwrap();
+            if slot.is_none() {
+                if let Some(c) = D::keepalive(p) {
+                    *slot = Some(Box::new(move |d: &mut D| d.send(c)));
+                }
+            }
+            true
+        });
+    }
+}
+
+impl<D: Driver> Supervisor for SupervisorForSingle<D>
+where
+    D::Key: Send + Clone + PartialEq + 'static,
+{
+    type Event<'a> = SupervisorEventForSingle<'a, D>;
+    type Report = ();
+
+    #[inline]
+    fn supervise<F>(&mut self, len: usize, f: F)
+    where
+        F: for<'a> FnMut(Self::Event<'a>) -> bool,
+    {
+        if len > 0 {
+            self.join(f);
+        }
+    }
+
+    #[inline]
+    fn metrics(&mut self) -> Arc<Metrics> {
+        SupervisorForSingle::metrics(self)
+    }
+
+    #[inline]
+    fn stop_handle(&self) -> Stop {
+        SupervisorForSingle::stop_handle(self)
+    }
+}
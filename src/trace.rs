@@ -0,0 +1,54 @@
+//! 可选的 `tracing` 集成，未启用 `tracing` 特性时所有记录都被忽略
+
+#[cfg(feature = "tracing")]
+use {
+    crate::Driver,
+    std::hash::{DefaultHasher, Hash, Hasher},
+};
+
+/// 日志中区分设备的标记，取 [`Driver::key_label`]，未提供时取键的散列值
+///
+/// 同一进程中同一个键的标记相同。
+#[cfg(feature = "tracing")]
+pub(crate) fn key_tag<D: Driver>(k: &D::Key) -> String
+where
+    D::Key: Hash,
+{
+    D::key_label(k).unwrap_or_else(|| {
+        let mut hasher = DefaultHasher::new();
+        k.hash(&mut hasher);
+        format!("#{:016x}", hasher.finish())
+    })
+}
+
+/// 设备线程的 span，线程中的记录都归于其下
+#[cfg(feature = "tracing")]
+pub(crate) fn device_span<D: Driver>(k: &D::Key) -> tracing::Span
+where
+    D::Key: Hash,
+{
+    tracing::debug_span!("device", key = %key_tag::<D>(k))
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)*);
+    };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)*);
+    };
+}
+
+pub(crate) use {debug, trace, warning};